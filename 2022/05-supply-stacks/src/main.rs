@@ -1,5 +1,7 @@
+use std::collections::{HashSet, VecDeque};
 use std::env;
 use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::vec::Vec;
 
@@ -7,24 +9,30 @@ fn main() -> Result<(), Box<dyn Error>> {
     let args = env::args().collect::<Vec<String>>();
 
     if args.len() < 2 {
-        return Err(Box::from("usage: cargo run -- puzzle-input"));
+        return Err(Box::from(
+            "usage: cargo run -- puzzle-input [plan (9000 | 9001) (tops | --drawing target-drawing)]"
+        ));
     }
 
     let (mut crane, moves) = read_puzzle(&args[1])?;
+
+    if args.len() > 2 {
+        return plan(&crane, &args[2..]);
+    }
     let mut crane_9001 = crane.clone();
 
     crane.start_crane(&moves);
 
     let part_one = crane.skim()
         .into_iter()
-        .filter_map(|top| top)
+        .flatten()
         .collect::<String>();
 
     crane_9001.start_crane_9001(&moves);
 
     let part_two = crane_9001.skim()
         .into_iter()
-        .filter_map(|top| top)
+        .flatten()
         .collect::<String>();
 
     println!("part one: {:?}", part_one);
@@ -33,120 +41,316 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-#[derive(Clone, Debug)]
-struct Crane(Vec<Vec<char>>);
+fn plan(crane: &Crane, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let model = match args.get(..2) {
+        Some([plan, model]) if plan == "plan" => match model.as_str() {
+            "9000" => Model::Crane9000,
+            "9001" => Model::Crane9001,
+            oops => Err(Box::<dyn Error>::from(format!("unexpected crane model `{}`", oops)))?,
+        },
+        _ => Err(Box::<dyn Error>::from("expect `plan` followed by a crane model"))?,
+    };
+
+    let target = match &args[2..] {
+        [flag, drawing] if flag == "--drawing" => {
+            let contents = fs::read_to_string(drawing)?;
+            let lines = contents.lines()
+                .take_while(|line| !line.trim().is_empty())
+                .collect::<Vec<&str>>();
+            Target::Drawing(read_crane(&lines)?)
+        }
+        [tops] => Target::Tops(read_tops(tops)),
+        _ => Err(Box::<dyn Error>::from("expect tops or `--drawing target-drawing`"))?,
+    };
+
+    for mov in crane.plan(model, &target, 1_000_000)? {
+        println!("{}", mov);
+    }
+
+    Ok(())
+}
+
+fn read_tops(tops: &str) -> Vec<Label> {
+    // Single letter labels can be run together like the puzzle answer, wider ones are separated
+    // by commas.
+    if tops.contains(',') {
+        tops.split(',').map(|label| label.trim().to_string()).collect()
+    } else {
+        tops.chars().map(|label| label.to_string()).collect()
+    }
+}
+
+// Labels are usually a single letter but wider warehouses use names like `AB`.
+type Label = String;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Crane(Vec<Vec<Label>>);
+
+#[derive(Clone, Copy, Debug)]
+enum Model {
+    Crane9000,
+    Crane9001,
+}
+
+#[derive(Debug)]
+enum Target {
+    Tops(Vec<Label>),
+    Drawing(Crane),
+}
 
 impl Crane {
-    fn start_crane(&mut self, moves: &Vec<Move>) {
-        let Crane(stacks) = self;
+    fn start_crane(&mut self, moves: &[Move]) {
+        // Popping crates one at a time and pushing them onto the other stack is the same as
+        // draining the top of the stack in reverse.
         for mov in moves {
-            for _ in 0..mov.quantity {
-                let grabbed = stacks[mov.from - 1].pop();
-                if let Some(label) = grabbed {
-                    stacks[mov.to - 1].push(label);
+            if let Some((from, to)) = self.pair(mov) {
+                let from_len = from.len();
+                let quantity = mov.quantity.min(from_len);
+                to.extend(from.drain(from_len - quantity..).rev());
+            }
+        }
+    }
+
+    fn start_crane_9001(&mut self, moves: &[Move]) {
+        for mov in moves {
+            if let Some((from, to)) = self.pair(mov) {
+                let from_len = from.len();
+                if from_len >= mov.quantity {
+                    to.extend(from.drain(from_len - mov.quantity..));
                 }
             }
         }
     }
 
-    fn start_crane_9001(&mut self, moves: &Vec<Move>) {
+    fn pair(&mut self, mov: &Move) -> Option<(&mut Vec<Label>, &mut Vec<Label>)> {
+        // Borrow both stacks at once so crates move without an intermediate copy. Moving crates
+        // onto the stack they came from doesn't change anything, so there's no pair to borrow.
+        if mov.from == mov.to {
+            return None;
+        }
+
         let Crane(stacks) = self;
-        for mov in moves {
-            let from = &stacks[mov.from - 1];
-            let from_len = from.len();
+        let [from, to] = stacks.get_disjoint_mut([mov.from - 1, mov.to - 1])
+            .expect("`read_moves` only lets through stacks that exist");
+        Some((from, to))
+    }
 
-            // Have to copy instead of using `iter::Extend` because can't have a mutable reference
-            // to `mov.to` and a reference to `mov.from` -- yikes.
+    fn start(&mut self, model: Model, moves: &[Move]) {
+        match model {
+            Model::Crane9000 => self.start_crane(moves),
+            Model::Crane9001 => self.start_crane_9001(moves),
+        }
+    }
 
-            if from_len >= mov.quantity {
-                let grabbed = &from[from_len - mov.quantity..].iter()
-                    .copied()
-                    .collect::<Vec<char>>();
+    fn plan(&self, model: Model, target: &Target, limit: usize) -> Result<Vec<Move>, String> {
+        // Breadth-first search over arrangements of crates, so the first arrangement that hits
+        // the target is reached with the fewest moves. Gives up after `limit` arrangements since
+        // the number of arrangements explodes with the number of crates.
 
-                stacks[mov.to - 1].extend(grabbed);
+        if let Target::Drawing(Crane(goal)) = target {
+            let mut want = goal.iter().flatten().collect::<Vec<&Label>>();
+            let mut have = self.0.iter().flatten().collect::<Vec<&Label>>();
+            want.sort_unstable();
+            have.sort_unstable();
 
-                stacks[mov.from - 1].truncate(from_len - mov.quantity);
+            if goal.len() != self.0.len() || want != have {
+                return Err("expect target drawing to rearrange the same crates".to_string());
             }
         }
+
+        // Each discovered arrangement remembers the move that reached it and where it came from.
+        let mut trail: Vec<(Option<usize>, Option<Move>)> = vec![(None, None)];
+        let mut discovered = VecDeque::from([(0, self.clone())]);
+        let mut seen = HashSet::from([self.clone()]);
+
+        while let Some((step, crane)) = discovered.pop_front() {
+            if crane.reaches(target) {
+                let mut moves = Vec::new();
+                let mut step = Some(step);
+
+                while let Some((parent, mov)) = step.map(|step| trail[step]) {
+                    moves.extend(mov);
+                    step = parent;
+                }
+
+                moves.reverse();
+                return Ok(moves);
+            }
+
+            for mov in crane.candidates() {
+                let mut next = crane.clone();
+                next.start(model, &[mov]);
+
+                if seen.len() >= limit {
+                    return Err(format!("no plan found within {} arrangements", limit));
+                }
+
+                if seen.insert(next.clone()) {
+                    trail.push((Some(step), Some(mov)));
+                    discovered.push_back((trail.len() - 1, next));
+                }
+            }
+        }
+
+        Err("target is unreachable".to_string())
+    }
+
+    fn candidates(&self) -> Vec<Move> {
+        let Crane(stacks) = self;
+        let mut moves = Vec::new();
+
+        for (from, stack) in (1..).zip(stacks) {
+            for to in (1..=stacks.len()).filter(|&to| to != from) {
+                for quantity in 1..=stack.len() {
+                    moves.push(Move { quantity, from, to });
+                }
+            }
+        }
+
+        moves
+    }
+
+    fn reaches(&self, target: &Target) -> bool {
+        match target {
+            Target::Tops(tops) =>
+                self.skim().into_iter().flatten().eq(tops.iter().map(|top| top.as_str())),
+            Target::Drawing(goal) =>
+                self == goal,
+        }
     }
 
-    fn skim(&self) -> Vec<Option<char>> {
+    fn skim(&self) -> Vec<Option<&str>> {
         let Crane(stacks) = self;
         stacks.iter()
-            .map(|stack| stack.last().map(|label| *label))
+            .map(|stack| stack.last().map(|label| label.as_str()))
             .collect()
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 struct Move {
     quantity: usize,
     from: usize,
     to: usize,
 }
 
-fn read_puzzle(puzzle: &str) -> Result<(Crane, Vec<Move>), Box<dyn Error>> {
-    // TODO: Splitting on consecutive line feeds isn't portable or potable.
-    // Or splitting on newlines in general for that matter.
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "move {} from {} to {}", self.quantity, self.from, self.to)
+    }
+}
 
+fn read_puzzle(puzzle: &str) -> Result<(Crane, Vec<Move>), Box<dyn Error>> {
     let contents = fs::read_to_string(puzzle)?;
-    let mut stacks_then_moves = contents.split("\n\n");
+    read_drawing(&contents)
+}
 
-    if let (Some(stacks), Some(moves)) = (stacks_then_moves.next(), stacks_then_moves.next()) {
-        Ok((read_crane(stacks)?, read_moves(moves.trim())?))
-    } else {
-        Err(Box::from("expect puzzle to be partitioned by '\\n\\n'"))
-    }
+fn read_drawing(contents: &str) -> Result<(Crane, Vec<Move>), Box<dyn Error>> {
+    // `lines` strips carriage returns, so look for the first blank line instead of splitting on
+    // consecutive line feeds.
+
+    let lines = contents.lines().collect::<Vec<&str>>();
+
+    let blank = lines.iter()
+        .position(|line| line.trim().is_empty())
+        .ok_or(Box::<dyn Error>::from("expect puzzle to be partitioned by a blank line"))?;
+
+    let crane = read_crane(&lines[..blank])?;
+    let moves = read_moves(&lines[blank + 1..], crane.0.len())?;
+
+    Ok((crane, moves))
 }
 
-fn read_crane(text: &str) -> Result<Crane, Box<dyn Error>> {
-    let mut lines = text.split('\n').rev();
+fn read_crane(lines: &[&str]) -> Result<Crane, Box<dyn Error>> {
+    // Crates aren't always four characters wide and indices can have more than one digit, so
+    // columns are found by lining up each crate with the stack index underneath it.
 
-    let indices = lines.next()
-        .ok_or(Box::<dyn Error>::from("expect stack indices"))?
-        .trim()
-        .split_ascii_whitespace()
-        .count();
+    let mut lines = lines.iter().rev();
 
-    let mut stacks = vec![Vec::new(); indices];
+    let indices = words(lines.next().ok_or(Box::<dyn Error>::from("expect stack indices"))?);
 
-    for line in lines {
-        let mut chars = line.chars();
+    for (location, (_, index)) in indices.iter().enumerate() {
+        if index.parse::<usize>().ok() != Some(location + 1) {
+            return Err(Box::from(format!("expect stack index {}, found `{}`", location + 1, index)));
+        }
+    }
 
-        for location in 0..indices {
-            let pallet = chars.by_ref().take(4).collect::<String>();
-            let label = pallet.chars().nth(1)
-                .ok_or(Box::<dyn Error>::from("expect pallet to have label"))?;
+    let mut stacks = vec![Vec::new(); indices.len()];
 
-            if label != ' ' {
-                stacks[location].push(label);
-            }
+    for line in lines {
+        for (start, pallet) in words(line) {
+            let end = start + pallet.chars().count();
+
+            let label = pallet.strip_prefix('[')
+                .and_then(|pallet| pallet.strip_suffix(']'))
+                .filter(|label| !label.is_empty())
+                .ok_or(Box::<dyn Error>::from(format!("expect pallet `[label]`, found `{}`", pallet)))?;
+
+            // The pallet belongs to the stack whose index shares at least one column with it.
+            let location = indices.iter()
+                .position(|(index_start, index)| {
+                    start < index_start + index.chars().count() && *index_start < end
+                })
+                .ok_or(Box::<dyn Error>::from(
+                    format!("expect pallet `{}` at column {} to sit above a stack index", pallet, start + 1)
+                ))?;
+
+            stacks[location].push(label.to_string());
         }
     }
 
     Ok(Crane(stacks))
 }
 
-fn read_moves(text: &str) -> Result<Vec<Move>, Box<dyn Error>> {
-    let lines = text.split('\n');
+fn words(line: &str) -> Vec<(usize, &str)> {
+    // Like `split_ascii_whitespace`, but remembers the column each word starts at.
+    let mut words = Vec::new();
+    let mut start = None;
+
+    for (column, (offset, symbol)) in line.char_indices().enumerate() {
+        match (start, symbol.is_whitespace()) {
+            (None, false) => start = Some((column, offset)),
+            (Some((column, begin)), true) => {
+                words.push((column, &line[begin..offset]));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+
+    if let Some((column, begin)) = start {
+        words.push((column, &line[begin..]));
+    }
 
+    words
+}
+
+fn read_moves(lines: &[&str], stacks: usize) -> Result<Vec<Move>, Box<dyn Error>> {
     let mut moves = Vec::new();
 
-    for line in lines {
+    for line in lines.iter().filter(|line| !line.trim().is_empty()) {
         let mut mov = line.split_ascii_whitespace();
 
-        let quantity = mov.by_ref().skip(1).next()
+        let quantity = mov.nth(1)
             .ok_or(Box::<dyn Error>::from("expect quantity in move"))?
             .parse::<usize>()?;
 
-        let from = mov.by_ref().skip(1).next()
+        let from = mov.nth(1)
             .ok_or(Box::<dyn Error>::from("expect from in move"))?
             .parse::<usize>()?;
 
-        let to = mov.by_ref().skip(1).next()
+        let to = mov.nth(1)
             .ok_or(Box::<dyn Error>::from("expect to in move"))?
             .parse::<usize>()?;
 
+        for stack in [from, to] {
+            if !(1..=stacks).contains(&stack) {
+                return Err(Box::from(format!(
+                    "expect stacks 1 to {} in `{}`, found {}", stacks, line.trim(), stack
+                )));
+            }
+        }
+
         moves.push(Move {
             quantity,
             from,
@@ -156,3 +360,182 @@ fn read_moves(text: &str) -> Result<Vec<Move>, Box<dyn Error>> {
 
     Ok(moves)
 }
+
+#[test]
+fn read_sample_drawing() -> Result<(), Box<dyn Error>> {
+    let sample = "    [D]    \n[N] [C]    \n[Z] [M] [P]\n 1   2   3 \n\nmove 1 from 2 to 1\n";
+
+    let (Crane(stacks), moves) = read_drawing(sample)?;
+
+    assert_eq!(stacks, vec![vec!["Z", "N"], vec!["M", "C", "D"], vec!["P"]]);
+    assert_eq!(moves.len(), 1);
+    assert_eq!((moves[0].quantity, moves[0].from, moves[0].to), (1, 2, 1));
+
+    Ok(())
+}
+
+#[test]
+fn read_wide_drawing_with_carriage_returns() -> Result<(), Box<dyn Error>> {
+    let sample = [
+        "                                        [K]",
+        "[AB]                                    [L]  [XYZ]",
+        "[C]  [D]  [E]  [F]  [G]  [H]  [I]  [J]  [M]  [N]",
+        " 1    2    3    4    5    6    7    8    9    10   ",
+        "",
+        "move 2 from 9 to 10",
+    ].join("\r\n");
+
+    let (mut crane, moves) = read_drawing(&sample)?;
+
+    assert_eq!(crane.0.len(), 10);
+    assert_eq!(crane.0[0], vec!["C", "AB"]);
+    assert_eq!(crane.0[8], vec!["M", "L", "K"]);
+    assert_eq!(crane.0[9], vec!["N", "XYZ"]);
+
+    crane.start_crane(&moves);
+
+    assert_eq!(crane.skim()[9], Some("L"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn generate_procedure(
+    stacks: usize, height: usize, moves: usize, most: usize
+) -> (Crane, Vec<Move>) {
+    // A linear congruential generator is plenty random for shuffling crates around.
+    let mut seed: u64 = 0x5eed;
+    let mut next = |bound: usize| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) as usize % bound
+    };
+
+    let crane = Crane(
+        (0..stacks)
+            .map(|stack| (0..height).map(|level| format!("{}-{}", stack, level)).collect())
+            .collect()
+    );
+
+    let moves = (0..moves)
+        .map(|_| Move { quantity: 1 + next(most), from: 1 + next(stacks), to: 1 + next(stacks) })
+        .collect();
+
+    (crane, moves)
+}
+
+#[test]
+fn bulk_moves_match_one_crate_at_a_time() {
+    let (crane, moves) = generate_procedure(5, 20, 500, 20);
+
+    let mut expected = crane.clone();
+    let mut expected_9001 = crane.clone();
+
+    for mov in &moves {
+        let Crane(stacks) = &mut expected;
+        for _ in 0..mov.quantity {
+            if let Some(label) = stacks[mov.from - 1].pop() {
+                stacks[mov.to - 1].push(label);
+            }
+        }
+
+        let Crane(stacks) = &mut expected_9001;
+        let from_len = stacks[mov.from - 1].len();
+        if from_len >= mov.quantity {
+            let grabbed = stacks[mov.from - 1].split_off(from_len - mov.quantity);
+            stacks[mov.to - 1].extend(grabbed);
+        }
+    }
+
+    let mut actual = crane.clone();
+    actual.start_crane(&moves);
+    assert_eq!(actual.0, expected.0);
+
+    let mut actual_9001 = crane;
+    actual_9001.start_crane_9001(&moves);
+    assert_eq!(actual_9001.0, expected_9001.0);
+}
+
+#[test]
+#[ignore]
+fn bench_generated_procedure() {
+    // Run with `cargo test --release -- --ignored --nocapture`.
+
+    use std::time::Instant;
+
+    let (crane, moves) = generate_procedure(50, 100_000, 2_000_000, 100);
+
+    let mut crane_9000 = crane.clone();
+    let now = Instant::now();
+    crane_9000.start_crane(&moves);
+    println!("crane 9000: {} moves in {:?}", moves.len(), now.elapsed());
+
+    let mut crane_9001 = crane;
+    let now = Instant::now();
+    crane_9001.start_crane_9001(&moves);
+    println!("crane 9001: {} moves in {:?}", moves.len(), now.elapsed());
+}
+
+#[test]
+fn reject_misaligned_pallet() {
+    let sample = "[A]\n    [B]\n 1 \n\nmove 1 from 1 to 1";
+    assert!(read_drawing(sample).is_err());
+}
+
+#[test]
+fn reject_missing_stacks() {
+    let sample = "[A]\n 1 \n\nmove 1 from 1 to 1\nmove 1 from 1 to 7";
+    assert_eq!(
+        read_drawing(sample).map_err(|error| error.to_string()).err(),
+        Some("expect stacks 1 to 1 in `move 1 from 1 to 7`, found 7".to_string())
+    );
+
+    let sample = "[A]\n 1 \n\nmove 1 from 0 to 1";
+    assert!(read_drawing(sample).is_err());
+}
+
+#[test]
+fn plan_sample_tops() -> Result<(), Box<dyn Error>> {
+    let sample = "    [D]    \n[N] [C]    \n[Z] [M] [P]\n 1   2   3 \n\nmove 1 from 2 to 1\n";
+
+    let (crane, _) = read_drawing(sample)?;
+
+    // The puzzle takes four moves. The 9000 can do it in two by flipping `ZN` onto stack 3 and
+    // `CD` onto stack 1, and one move can't change all three tops. The 9001 can't flip pairs of
+    // crates, so it needs all four.
+    for (model, fewest) in [(Model::Crane9000, 2), (Model::Crane9001, 4)] {
+        let moves = crane.plan(model, &Target::Tops(read_tops("CMZ")), 100_000)?;
+
+        assert_eq!(moves.len(), fewest);
+
+        let mut check = crane.clone();
+        check.start(model, &moves);
+        assert_eq!(check.skim().into_iter().flatten().collect::<String>(), "CMZ");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn plan_sample_drawing() -> Result<(), Box<dyn Error>> {
+    let sample = "    [D]    \n[N] [C]    \n[Z] [M] [P]\n 1   2   3 \n\nmove 1 from 2 to 1\n";
+    let target = "        [Z]\n        [N]\n        [D]\n[C] [M] [P]\n 1   2   3 ";
+
+    let (crane, _) = read_drawing(sample)?;
+    let goal = read_crane(&target.lines().collect::<Vec<&str>>())?;
+
+    let moves = crane.plan(Model::Crane9001, &Target::Drawing(goal.clone()), 100_000)?;
+
+    // Printed moves read back as a procedure.
+    let procedure = moves.iter().map(|mov| mov.to_string()).collect::<Vec<String>>();
+    let procedure = procedure.iter().map(|mov| mov.as_str()).collect::<Vec<&str>>();
+    let procedure = read_moves(&procedure, 3)?;
+    assert_eq!(procedure, moves);
+
+    let mut check = crane.clone();
+    check.start_crane_9001(&procedure);
+    assert_eq!(check, goal);
+
+    assert!(crane.plan(Model::Crane9001, &Target::Drawing(Crane(vec![vec![]; 3])), 100).is_err());
+
+    Ok(())
+}
@@ -1,12 +1,63 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::env;
 use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+
+// The CRT draws its sprite from `x`, the rest of the registers are scratch space for programs.
+const REGISTERS: [&str; 4] = ["x", "a", "b", "c"];
+
+// The capital letters the CRT can display. Each is four pixels wide and six tall, and letters are
+// separated by a dark column. There's no blank glyph, a dark screen isn't an answer.
+const GLYPH_WIDTH: i32 = 4;
+const GLYPH_HEIGHT: i32 = 6;
+
+const FONT: &[(char, [&str; GLYPH_HEIGHT as usize])] = &[
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+// The longest line a plain Netpbm image should have.
+const NETPBM_LINE: usize = 70;
+
+// Programs can loop forever, so stop looking at the register after this many cycles.
+const CYCLE_LIMIT: i32 = 1_000_000;
+
+type Reg = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Value {
+    Reg(Reg),
+    Imm(i32),
+}
 
 #[derive(Debug, PartialEq)]
 enum Stmt {
     Addx(i32),
-    Noop
+    Noop,
+    Set(Reg, Value),
+    Add(Reg, Value),
+    Mul(Reg, Value),
+    Jmp(i32),
+    Jnz(Value, i32),
+    Jgz(Value, i32),
 }
 
 #[derive(Debug, PartialEq)]
@@ -15,142 +66,854 @@ struct Ins {
     cycles: i32,
 }
 
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Reg(register) => write!(f, "{}", REGISTERS[*register]),
+            Value::Imm(immediate) => write!(f, "{}", immediate),
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stmt::Addx(increment) => write!(f, "addx {}", increment),
+            Stmt::Noop => write!(f, "noop"),
+            Stmt::Set(register, value) => write!(f, "set {} {}", REGISTERS[*register], value),
+            Stmt::Add(register, value) => write!(f, "add {} {}", REGISTERS[*register], value),
+            Stmt::Mul(register, value) => write!(f, "mul {} {}", REGISTERS[*register], value),
+            Stmt::Jmp(offset) => write!(f, "jmp {}", offset),
+            Stmt::Jnz(value, offset) => write!(f, "jnz {} {}", value, offset),
+            Stmt::Jgz(value, offset) => write!(f, "jgz {} {}", value, offset),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operand {
+    Register,
+    Immediate,
+    Either,
+}
+
+struct Def {
+    mnemonic: &'static str,
+    operands: &'static [Operand],
+    cycles: i32,
+    // Only called with values that match `operands`.
+    build: fn(&[Value]) -> Stmt,
+}
+
+use self::Operand::*;
+
+// Jumps are relative to the jumping instruction, so `jmp -1` jumps to the instruction before it.
+const INSTRUCTION_SET: &[Def] = &[
+    Def { mnemonic: "noop", operands: &[], cycles: 1, build: |_| Stmt::Noop },
+    Def { mnemonic: "addx", operands: &[Immediate], cycles: 2, build: |args| match args {
+        &[Value::Imm(increment)] => Stmt::Addx(increment),
+        _ => unreachable!(),
+    }},
+    Def { mnemonic: "set", operands: &[Register, Either], cycles: 1, build: |args| match args {
+        &[Value::Reg(register), value] => Stmt::Set(register, value),
+        _ => unreachable!(),
+    }},
+    Def { mnemonic: "add", operands: &[Register, Either], cycles: 2, build: |args| match args {
+        &[Value::Reg(register), value] => Stmt::Add(register, value),
+        _ => unreachable!(),
+    }},
+    Def { mnemonic: "mul", operands: &[Register, Either], cycles: 4, build: |args| match args {
+        &[Value::Reg(register), value] => Stmt::Mul(register, value),
+        _ => unreachable!(),
+    }},
+    Def { mnemonic: "jmp", operands: &[Immediate], cycles: 1, build: |args| match args {
+        &[Value::Imm(offset)] => Stmt::Jmp(offset),
+        _ => unreachable!(),
+    }},
+    Def { mnemonic: "jnz", operands: &[Either, Immediate], cycles: 2, build: |args| match args {
+        &[value, Value::Imm(offset)] => Stmt::Jnz(value, offset),
+        _ => unreachable!(),
+    }},
+    Def { mnemonic: "jgz", operands: &[Either, Immediate], cycles: 2, build: |args| match args {
+        &[value, Value::Imm(offset)] => Stmt::Jgz(value, offset),
+        _ => unreachable!(),
+    }},
+];
+
 fn main() -> Result<(), Box<dyn Error>> {
     let args = env::args().collect::<Vec<String>>();
 
     if args.len() < 2 {
-        return Err(Box::from("usage: cargo run -- puzzle-input"));
+        return Err(Box::from(
+            "usage: cargo run -- puzzle-input [--debug | --trace (csv | json)] [--height n] \
+             [--width n] [--sprite n] [--ascii | --emoji | --pbm | --pgm] [--lit pixel] \
+             [--dark pixel] [--output file]"
+        ));
     }
 
     let puzzle = fs::read_to_string(&args[1])?;
     let instructions = read_puzzle(&puzzle)?;
+    let options = read_options(&args[2..])?;
+
+    if options.debug {
+        let mut debugger = Debugger::new(&instructions, options.crt);
+        debugger.session(io::stdin().lock(), &mut io::stdout())?;
+        return Ok(());
+    }
+
+    if let Some(format) = options.trace {
+        let records = trace(&instructions, &options.crt)?;
+        let trace = match format {
+            Trace::Csv => trace_csv(&records),
+            Trace::Json => trace_json(&records),
+        };
+
+        match options.output {
+            Some(output) => fs::write(output, trace)?,
+            None => print!("{}", trace),
+        }
+
+        return Ok(());
+    }
 
     let desired = (20..=220).step_by(40).collect::<Vec<i32>>();
-    let values = read_register(&instructions, &desired);
+    let values = read_register(&instructions, &desired)?;
 
     let part_one =
         desired.iter()
         .zip(values.iter())
-        .map(|(&cycle, &value)| cycle * value)
-        .sum::<i32>();
+        .map(|(&cycle, &value)| cycle as i64 * value as i64)
+        .sum::<i64>();
 
     println!("part one: {:?}", part_one);
 
-    println!("part two");
-    render(&instructions);
+    let lit = draw(&instructions, &options.crt)?;
+
+    // Show the picture if the letters can't be read or somebody asked for it.
+    let picture = match read_letters(&lit, &options.crt) {
+        Ok(letters) => {
+            println!("part two: {:?}", letters);
+            options.picture
+        }
+        Err(unrecognized) => {
+            println!("part two: {}", unrecognized);
+            Some(options.picture.unwrap_or_else(Picture::emoji))
+        }
+    };
+
+    if let Some(picture) = picture {
+        let image = render(&lit, &options.crt, &picture);
+
+        match options.output {
+            Some(output) => fs::write(output, image)?,
+            None => print!("{}", image),
+        }
+    }
 
     Ok(())
 }
 
-fn read_register(instructions: &[Ins], cycles: &[i32]) -> Vec<i32> {
-    // TODO: Slow, use a tree and find the largest key less than or equal to the desired cycle.
-    // The floor of the desired cycle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Trace {
+    Csv,
+    Json,
+}
+
+#[derive(Debug)]
+struct Options {
+    debug: bool,
+    trace: Option<Trace>,
+    crt: Crt,
+    picture: Option<Picture>,
+    output: Option<String>,
+}
+
+fn read_options(args: &[String]) -> Result<Options, Box<dyn Error>> {
+    let mut options = Options {
+        debug: false, trace: None,
+        crt: Crt::default(), picture: None,
+        output: None,
+    };
+    let mut pixels: (Option<String>, Option<String>) = (None, None);
 
-    let measures = inspect_register(instructions);
-    let mut values = Vec::new();
+    let mut args = args.iter();
 
-    for &cycle in cycles {
-        let register = measures.iter()
-            .rev()
-            // Will fail only if cycle is non-positive. If the cycle is invalid, ignore it.
-            .find(|&&(rising_edge, _)| rising_edge <= cycle)
-            .map(|measure| measure.1);
+    while let Some(flag) = args.next() {
+        let mut value = || args.next().ok_or(format!("expect value after `{}`", flag));
 
-        if let Some(value) = register {
-            values.push(value);
+        match flag.as_str() {
+            "--debug" => options.debug = true,
+            "--trace" => options.trace = match value()?.as_str() {
+                "csv" => Some(Trace::Csv),
+                "json" => Some(Trace::Json),
+                oops => Err(format!("unexpected trace format `{}`", oops))?,
+            },
+            "--height" => options.crt.height = value()?.parse()?,
+            "--width" => options.crt.width = value()?.parse()?,
+            "--sprite" => options.crt.sprite_width = value()?.parse()?,
+            "--ascii" => options.picture = Some(Picture::ascii()),
+            "--emoji" => options.picture = Some(Picture::emoji()),
+            "--pbm" => options.picture = Some(Picture::Pbm),
+            "--pgm" => options.picture = Some(Picture::Pgm),
+            "--lit" => pixels.0 = Some(value()?.to_string()),
+            "--dark" => pixels.1 = Some(value()?.to_string()),
+            "--output" => options.output = Some(value()?.to_string()),
+            oops => Err(format!("unexpected option `{}`", oops))?,
         }
     }
 
-    values
+    if options.crt.height < 1 || options.crt.width < 1 || options.crt.sprite_width < 1 {
+        Err("expect CRT dimensions and sprite width to be positive")?
+    }
+
+    // Drawing counts cycles up to one past the last pixel, so that has to fit too.
+    let (height, width) = (options.crt.height, options.crt.width);
+
+    if height.checked_mul(width).and_then(|pixels| pixels.checked_add(1)).is_none() {
+        Err(format!("expect CRT with fewer pixels, {} by {} is too many", height, width))?
+    }
+
+    // Custom pixels only make sense for text, and start from emoji unless ASCII was asked for.
+    if pixels != (None, None) {
+        let (lit, dark) = match options.picture.take().unwrap_or_else(Picture::emoji) {
+            Picture::Text { lit, dark } => (lit, dark),
+            _ => Err("expect `--lit` and `--dark` to be used with a text picture")?,
+        };
+
+        options.picture = Some(Picture::Text {
+            lit: pixels.0.unwrap_or(lit),
+            dark: pixels.1.unwrap_or(dark),
+        });
+    }
+
+    Ok(options)
 }
 
-fn inspect_register(instructions: &[Ins]) -> Vec<(i32, i32)> {
-    // For a pair `(n, x)`, at the start of cycle `n` the value of the register is `x`.
-    let mut measurements = Vec::new();
+#[derive(Clone, Debug)]
+struct Cpu {
+    registers: [i32; REGISTERS.len()],
+    // Index of the next instruction to execute.
+    pointer: usize,
+    // The cycle the next instruction starts on.
+    cycle: i32,
+}
+
+impl Cpu {
+    fn new() -> Self {
+        Cpu { registers: [1, 0, 0, 0], pointer: 0, cycle: 1 }
+    }
+
+    fn x(&self) -> i32 {
+        self.registers[0]
+    }
 
-    let mut register = 1;
-    let mut cycle = 1;
+    fn read(&self, value: Value) -> i32 {
+        match value {
+            Value::Reg(register) => self.registers[register],
+            Value::Imm(immediate) => immediate,
+        }
+    }
 
-    measurements.push((cycle, register));
+    fn step<'a>(&mut self, instructions: &'a [Ins]) -> Result<Option<&'a Ins>, String> {
+        // Execute one instruction. The registers change at the end of its last cycle, so they
+        // hold their new values at the start of `self.cycle`. Overflowing a register is an error
+        // and leaves the CPU as it was.
 
-    for instruction in instructions {
-        cycle += instruction.cycles;
+        let instruction = match instructions.get(self.pointer) {
+            Some(instruction) => instruction,
+            None => return Ok(None),
+        };
 
-        if let &Ins { stmt: Stmt::Addx(increment), .. } = instruction {
-            if increment != 0 {
-                register += increment;
-                measurements.push((cycle, register));
+        // Instructions count from zero, same as in the debugger and the trace.
+        let overflow = || {
+            format!("`{}` at instruction {} overflows", instruction.stmt, self.pointer)
+        };
+
+        let mut jump = 1;
+
+        match instruction.stmt {
+            Stmt::Addx(increment) => {
+                self.registers[0] = self.registers[0].checked_add(increment).ok_or_else(overflow)?
+            }
+            Stmt::Noop => (),
+            Stmt::Set(register, value) => self.registers[register] = self.read(value),
+            Stmt::Add(register, value) => {
+                self.registers[register] = self.registers[register]
+                    .checked_add(self.read(value))
+                    .ok_or_else(overflow)?
             }
+            Stmt::Mul(register, value) => {
+                self.registers[register] = self.registers[register]
+                    .checked_mul(self.read(value))
+                    .ok_or_else(overflow)?
+            }
+            Stmt::Jmp(offset) => jump = offset,
+            Stmt::Jnz(value, offset) => if self.read(value) != 0 { jump = offset },
+            Stmt::Jgz(value, offset) => if self.read(value) > 0 { jump = offset },
+        }
+
+        // Jumping before the first instruction halts the program just like running off the end.
+        self.pointer = self.pointer.checked_add_signed(jump as isize).unwrap_or(usize::MAX);
+        self.cycle += instruction.cycles;
+
+        Ok(Some(instruction))
+    }
+}
+
+// The value of the register over time. For an entry `(n, x)`, the register holds `x` from the
+// start of cycle `n` until the next entry.
+#[derive(Debug)]
+struct Timeline(BTreeMap<i32, i32>);
+
+impl Timeline {
+    fn new(instructions: &[Ins]) -> Result<Self, String> {
+        Ok(Timeline(inspect_register(instructions)?.into_iter().collect()))
+    }
+
+    fn at(&self, cycle: i32) -> Option<i32> {
+        // The floor of the desired cycle. Fails only if the cycle is non-positive.
+        self.0.range(..=cycle).next_back().map(|(_, &value)| value)
+    }
+
+    fn during(&self, cycles: Range<i32>) -> Vec<(i32, i32)> {
+        // The value at the start of `cycles` followed by every change before it ends.
+        let Range { start, end } = cycles;
+
+        if start >= end {
+            return Vec::new();
+        }
+
+        let first = self.at(start).map(|value| (start, value));
+
+        first.into_iter()
+            .chain(self.0.range(start + 1..end).map(|(&cycle, &value)| (cycle, value)))
+            .collect()
+    }
+}
+
+fn read_register(instructions: &[Ins], cycles: &[i32]) -> Result<Vec<i32>, String> {
+    let timeline = Timeline::new(instructions)?;
+
+    // If the cycle is invalid, ignore it.
+    Ok(cycles.iter()
+        .filter_map(|&cycle| timeline.at(cycle))
+        .collect())
+}
+
+fn execute<F: FnMut(&Cpu, &Ins, &Cpu)>(instructions: &[Ins], mut visit: F) -> Result<(), String> {
+    // Run the program, showing `visit` the CPU before and after each instruction.
+    let mut cpu = Cpu::new();
+
+    while cpu.cycle <= CYCLE_LIMIT {
+        let before = cpu.clone();
+
+        match cpu.step(instructions)? {
+            Some(instruction) => visit(&before, instruction, &cpu),
+            None => break,
         }
     }
 
-    measurements
+    Ok(())
 }
 
-fn render(instructions: &[Ins]) {
-    let crt_height = 6;
-    let crt_width = 40;
-    let crt_pixels = crt_height * crt_width;
+fn inspect_register(instructions: &[Ins]) -> Result<Vec<(i32, i32)>, String> {
+    // For a pair `(n, x)`, at the start of cycle `n` the value of the register is `x`.
+    let cpu = Cpu::new();
+    let mut measurements = vec![(cpu.cycle, cpu.x())];
+
+    execute(instructions, |before, _, after| {
+        if after.x() != before.x() {
+            measurements.push((after.cycle, after.x()));
+        }
+    })?;
 
-    let measures = inspect_register(&instructions);
-    let mut lit = HashSet::new();
+    Ok(measurements)
+}
+
+#[derive(Debug, PartialEq)]
+struct Record {
+    cycle: i32,
+    pointer: usize,
+    instruction: String,
+    // The register during the cycle and once the cycle is over.
+    x_before: i32,
+    x_after: i32,
+    // The pixel drawn during the cycle, if the CRT hasn't finished its frame.
+    pixel: Option<((i32, i32), bool)>,
+    signal_strength: i64,
+}
+
+fn trace(instructions: &[Ins], crt: &Crt) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+
+    execute(instructions, |before, instruction, after| {
+        for cycle in before.cycle..after.cycle {
+            let last = cycle == after.cycle - 1;
+
+            records.push(Record {
+                cycle,
+                pointer: before.pointer,
+                instruction: instruction.stmt.to_string(),
+                x_before: before.x(),
+                x_after: if last { after.x() } else { before.x() },
+                pixel: (cycle <= crt.pixels()).then(|| crt.pixel(cycle, before.x())),
+                signal_strength: cycle as i64 * before.x() as i64,
+            });
+        }
+    })?;
+
+    Ok(records)
+}
 
-    for i in 0..measures.len() {
-        let sprite_center = measures[i].1;
+fn trace_csv(records: &[Record]) -> String {
+    let mut csv = "cycle,pointer,instruction,x_before,x_after,row,column,lit,signal_strength\n"
+        .to_string();
 
-        let (cycle_start, cycle_end) = if i < measures.len() - 1 {
-            (measures[i].0, measures[i + 1].0 - 1)
-        } else {
-            (measures[i].0, crt_pixels)
+    for record in records {
+        let pixel = match record.pixel {
+            Some(((row, column), lit)) => format!("{},{},{}", row, column, lit),
+            None => ",,".to_string(),
         };
 
-        let (draw_start, draw_end) = (
-            (cycle_start - 1) % crt_width,
-            (cycle_end - 1) % crt_width
-        );
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            record.cycle, record.pointer, record.instruction,
+            record.x_before, record.x_after,
+            pixel,
+            record.signal_strength,
+        ));
+    }
+
+    csv
+}
+
+fn trace_json(records: &[Record]) -> String {
+    // Instructions are made of mnemonics, register names and integers, so they don't need
+    // escaping.
+    let records = records.iter()
+        .map(|record| {
+            let pixel = match record.pixel {
+                Some(((row, column), lit)) =>
+                    format!("{{\"row\": {}, \"column\": {}, \"lit\": {}}}", row, column, lit),
+                None =>
+                    "null".to_string(),
+            };
+
+            format!(
+                "  {{\"cycle\": {}, \"pointer\": {}, \"instruction\": \"{}\", \"x_before\": {}, \
+                 \"x_after\": {}, \"pixel\": {}, \"signal_strength\": {}}}",
+                record.cycle, record.pointer, record.instruction,
+                record.x_before, record.x_after,
+                pixel,
+                record.signal_strength,
+            )
+        })
+        .collect::<Vec<String>>();
+
+    format!("[\n{}\n]\n", records.join(",\n"))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Crt {
+    height: i32,
+    width: i32,
+    // The sprite is centered on `x`, leaning right when its width is even.
+    sprite_width: i32,
+}
+
+impl Default for Crt {
+    fn default() -> Self {
+        Crt { height: 6, width: 40, sprite_width: 3 }
+    }
+}
+
+impl Crt {
+    fn pixels(&self) -> i32 {
+        self.height * self.width
+    }
+
+    fn pixel(&self, cycle: i32, sprite_center: i32) -> ((i32, i32), bool) {
+        // During cycle `n` the CRT draws pixel `n - 1`, which is lit if the sprite covers it.
+        let (row, column) = ((cycle - 1) / self.width, (cycle - 1) % self.width);
+        // Registers can hold anything, so work out the sprite without overflowing.
+        let sprite_left = sprite_center as i64 - (self.sprite_width as i64 - 1) / 2;
+        let sprite = sprite_left..sprite_left + self.sprite_width as i64;
+        ((row, column), sprite.contains(&(column as i64)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Picture {
+    Text { lit: String, dark: String },
+    // Plain black and white and grayscale Netpbm images. Lit pixels are white like on the CRT.
+    Pbm,
+    Pgm,
+}
+
+impl Picture {
+    fn emoji() -> Self {
+        Picture::Text { lit: "🟨".to_string(), dark: "⬛️".to_string() }
+    }
 
-        for sprite in [sprite_center - 1, sprite_center, sprite_center + 1] {
-            if draw_start <= sprite && sprite <= draw_end {
-                lit.insert((cycle_start / crt_width, sprite));
+    fn ascii() -> Self {
+        Picture::Text { lit: "#".to_string(), dark: ".".to_string() }
+    }
+}
+
+fn draw(instructions: &[Ins], crt: &Crt) -> Result<HashSet<(i32, i32)>, String> {
+    // Walk the stretches of cycles where the sprite stays put instead of looking up every cycle.
+    let pixels = crt.pixels();
+    let sprites = Timeline::new(instructions)?.during(1..pixels + 1);
+
+    let ends = sprites.iter()
+        .skip(1)
+        .map(|&(cycle, _)| cycle)
+        .chain([pixels + 1]);
+
+    let lit = sprites.iter()
+        .zip(ends)
+        .flat_map(|(&(start, sprite_center), end)| {
+            (start..end).map(move |cycle| crt.pixel(cycle, sprite_center))
+        })
+        .filter_map(|(position, lit)| lit.then_some(position))
+        .collect();
+
+    Ok(lit)
+}
+
+fn render(lit: &HashSet<(i32, i32)>, crt: &Crt, picture: &Picture) -> String {
+    let mut image = match picture {
+        Picture::Text { .. } => String::new(),
+        Picture::Pbm => format!("P1\n{} {}\n", crt.width, crt.height),
+        Picture::Pgm => format!("P2\n{} {}\n255\n", crt.width, crt.height),
+    };
+
+    for row in 0..crt.height {
+        let pixels = (0..crt.width).map(|column| {
+            let on = lit.contains(&(row, column));
+            match picture {
+                Picture::Text { lit, dark } => if on { lit.as_str() } else { dark.as_str() },
+                Picture::Pbm => if on { "0" } else { "1" },
+                Picture::Pgm => if on { "255" } else { "0" },
             }
+        });
+
+        if let Picture::Text { .. } = picture {
+            image.push_str(&pixels.collect::<String>());
+            image.push('\n');
+            continue;
         }
-    }
 
-    for row in 0..crt_height {
-        for column in 0..crt_width {
-            if lit.contains(&(row, column)) {
-                print!("🟨");
+        // Netpbm wants lines of at most 70 characters, so long rows carry on over a few lines.
+        let mut line = String::new();
+
+        for pixel in pixels {
+            if line.is_empty() {
+                line.push_str(pixel);
+            } else if line.len() + 1 + pixel.len() <= NETPBM_LINE {
+                line.push(' ');
+                line.push_str(pixel);
             } else {
-                print!("⬛️");
+                image.push_str(&line);
+                image.push('\n');
+                line = pixel.to_string();
             }
         }
-        println!();
+
+        image.push_str(&line);
+        image.push('\n');
+    }
+
+    image
+}
+
+fn read_letters(lit: &HashSet<(i32, i32)>, crt: &Crt) -> Result<String, String> {
+    // Letters fill the screen from top to bottom, so on any other screen it's a picture.
+    if crt.height != GLYPH_HEIGHT {
+        return Err(format!("expect CRT {} pixels tall to read letters", GLYPH_HEIGHT));
+    }
+
+    let mut letters = String::new();
+    let mut unrecognized = Vec::new();
+
+    for position in 0..(crt.width + 1) / (GLYPH_WIDTH + 1) {
+        let left = position * (GLYPH_WIDTH + 1);
+
+        let glyph = (0..GLYPH_HEIGHT)
+            .map(|row| {
+                (left..left + GLYPH_WIDTH)
+                    .map(|column| if lit.contains(&(row, column)) { '#' } else { '.' })
+                    .collect::<String>()
+            })
+            .collect::<Vec<String>>();
+
+        match FONT.iter().find(|(_, pixels)| pixels[..] == glyph[..]) {
+            Some(&(letter, _)) => letters.push(letter),
+            None => {
+                letters.push('?');
+                unrecognized.push((position, glyph));
+            }
+        }
+    }
+
+    if unrecognized.is_empty() {
+        return Ok(letters);
+    }
+
+    let mut message = format!("unrecognized glyphs in {:?}", letters);
+
+    for (position, glyph) in unrecognized {
+        message.push_str(&format!("\nglyph {}:\n{}", position, glyph.join("\n")));
+    }
+
+    Err(message)
+}
+
+#[derive(Debug, PartialEq)]
+enum Breakpoint {
+    Cycle(i32),
+    Instruction(usize),
+}
+
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Stepped,
+    Breakpoint,
+    Watch(Reg, i32, i32),
+    Halted,
+    CycleLimit,
+    Overflow(String),
+}
+
+struct Debugger<'a> {
+    instructions: &'a [Ins],
+    crt: Crt,
+    cpu: Cpu,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Reg>,
+    // Pixels drawn so far and whether they're lit.
+    drawn: Vec<((i32, i32), bool)>,
+}
+
+impl<'a> Debugger<'a> {
+    fn new(instructions: &'a [Ins], crt: Crt) -> Self {
+        Debugger {
+            instructions,
+            crt,
+            cpu: Cpu::new(),
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            drawn: Vec::new(),
+        }
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        // A cycle breakpoint stops before the instruction that's running during that cycle.
+        let cycles = self.instructions.get(self.cpu.pointer).map(|ins| ins.cycles).unwrap_or(1);
+
+        self.breakpoints.iter().any(|breakpoint| match *breakpoint {
+            Breakpoint::Cycle(cycle) => self.cpu.cycle <= cycle && cycle < self.cpu.cycle + cycles,
+            Breakpoint::Instruction(index) => self.cpu.pointer == index,
+        })
+    }
+
+    fn run(&mut self, steps: Option<usize>) -> Stop {
+        // Execute `steps` instructions, or until something interesting happens if there's no
+        // limit. Always executes at least one instruction so a breakpoint doesn't stop us twice.
+
+        let mut executed = 0;
+
+        loop {
+            if steps == Some(executed) {
+                return Stop::Stepped;
+            }
+
+            if executed > 0 && self.at_breakpoint() {
+                return Stop::Breakpoint;
+            }
+
+            if self.cpu.cycle > CYCLE_LIMIT {
+                return Stop::CycleLimit;
+            }
+
+            let (start, sprite_center) = (self.cpu.cycle, self.cpu.x());
+            let before = self.cpu.registers;
+
+            match self.cpu.step(self.instructions) {
+                Ok(Some(_)) => (),
+                Ok(None) => return Stop::Halted,
+                Err(message) => return Stop::Overflow(message),
+            }
+
+            executed += 1;
+
+            let cycles = start..self.cpu.cycle;
+
+            for cycle in cycles.take_while(|&cycle| cycle <= self.crt.pixels()) {
+                self.drawn.push(self.crt.pixel(cycle, sprite_center));
+            }
+
+            for &register in &self.watches {
+                if before[register] != self.cpu.registers[register] {
+                    return Stop::Watch(register, before[register], self.cpu.registers[register]);
+                }
+            }
+        }
+    }
+
+    fn session<R: BufRead, W: Write>(&mut self, input: R, output: &mut W) -> io::Result<()> {
+        writeln!(output, "commands: step [n], continue, break (cycle | ins) n, delete")?;
+        writeln!(output, "          watch register, info, crt, quit")?;
+        self.info(output)?;
+
+        for line in input.lines() {
+            let line = line?;
+            let words = line.split_ascii_whitespace().collect::<Vec<&str>>();
+
+            let stop = match words[..] {
+                [] => continue,
+                ["step"] => Some(self.run(Some(1))),
+                ["step", steps] => match steps.parse::<usize>() {
+                    Ok(steps) => Some(self.run(Some(steps))),
+                    Err(_) => {
+                        writeln!(output, "expect number of steps, found `{}`", steps)?;
+                        None
+                    }
+                }
+                ["continue"] => Some(self.run(None)),
+                ["break", kind, at] => {
+                    let breakpoint = match kind {
+                        "cycle" => at.parse::<i32>().ok().map(Breakpoint::Cycle),
+                        "ins" => at.parse::<usize>().ok().map(Breakpoint::Instruction),
+                        _ => None,
+                    };
+                    match breakpoint {
+                        Some(breakpoint) => self.breakpoints.push(breakpoint),
+                        None => writeln!(output, "expect `break cycle n` or `break ins n`")?,
+                    }
+                    None
+                }
+                ["delete"] => { self.breakpoints.clear(); None }
+                ["watch", name] => {
+                    match REGISTERS.iter().position(|&register| register == name) {
+                        Some(register) => self.watches.push(register),
+                        None => writeln!(output, "unexpected register `{}`", name)?,
+                    }
+                    None
+                }
+                ["info"] => { self.info(output)?; None }
+                ["crt"] => { self.crt(output)?; None }
+                ["quit"] => break,
+                _ => { writeln!(output, "unexpected command `{}`", line.trim())?; None }
+            };
+
+            match stop {
+                None => (),
+                Some(Stop::Watch(register, before, after)) => {
+                    let name = REGISTERS[register];
+                    writeln!(output, "watch: {} changed from {} to {}", name, before, after)?;
+                    self.info(output)?;
+                }
+                Some(Stop::Halted) => writeln!(output, "halted at cycle {}", self.cpu.cycle)?,
+                Some(Stop::CycleLimit) => writeln!(output, "gave up at cycle {}", self.cpu.cycle)?,
+                Some(Stop::Overflow(message)) => {
+                    writeln!(output, "stopped at cycle {}: {}", self.cpu.cycle, message)?
+                }
+                Some(Stop::Breakpoint) => {
+                    writeln!(output, "breakpoint")?;
+                    self.info(output)?;
+                }
+                Some(Stop::Stepped) => self.info(output)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn info<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let registers = REGISTERS.iter()
+            .zip(self.cpu.registers.iter())
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        write!(output, "cycle {} {} ", self.cpu.cycle, registers)?;
+
+        match self.instructions.get(self.cpu.pointer) {
+            Some(instruction) =>
+                writeln!(output, "next {}: {}", self.cpu.pointer, instruction.stmt),
+            None => writeln!(output, "halted"),
+        }
+    }
+
+    fn crt<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        // Pixels the CRT hasn't reached yet are left blank.
+        let mut rows = vec![String::new(); self.crt.height as usize];
+
+        for &((row, _), lit) in &self.drawn {
+            rows[row as usize].push(if lit { '#' } else { '.' });
+        }
+
+        for row in rows {
+            writeln!(output, "{}", row)?;
+        }
+
+        Ok(())
     }
 }
 
 fn read_puzzle(puzzle: &str) -> Result<Vec<Ins>, Box<dyn Error>> {
     let mut instructions = Vec::new();
 
-    for line in puzzle.lines() {
+    // Blank lines and lines starting with `#` are ignored so programs can be commented.
+    let lines = puzzle.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+    for line in lines {
         let mut pieces = line.split_ascii_whitespace();
-        let instruction = pieces.next().ok_or("expect `addx` or `noop`")?;
-        instructions.push(match instruction {
-            "addx" => {
-                let increment = pieces.next()
-                    .ok_or("expect `addx` to have an argument")?
-                    .parse::<i32>()?;
-                Ins { stmt: Stmt::Addx(increment), cycles: 2 }
-            },
-            "noop" =>
-                Ins { stmt: Stmt::Noop, cycles: 1 },
-            oops =>
-                Err(Box::<dyn Error>::from(format!("unexpected instruction `{}`", oops)))?
-        });
+
+        let mnemonic = pieces.next().ok_or("expect instruction")?;
+
+        let def = INSTRUCTION_SET.iter()
+            .find(|def| def.mnemonic == mnemonic)
+            .ok_or(format!("unexpected instruction `{}`", mnemonic))?;
+
+        let mut args = Vec::new();
+
+        for &operand in def.operands {
+            let piece = pieces.next()
+                .ok_or(format!("expect `{}` to have {} arguments", mnemonic, def.operands.len()))?;
+            args.push(read_operand(piece, operand)?);
+        }
+
+        if let Some(extra) = pieces.next() {
+            Err(format!("unexpected argument `{}` to `{}`", extra, mnemonic))?
+        }
+
+        instructions.push(Ins { stmt: (def.build)(&args), cycles: def.cycles });
     }
 
     Ok(instructions)
 }
 
+fn read_operand(piece: &str, operand: Operand) -> Result<Value, Box<dyn Error>> {
+    let value = match REGISTERS.iter().position(|&register| register == piece) {
+        Some(register) => Value::Reg(register),
+        None => Value::Imm(piece.parse::<i32>()
+            .map_err(|_| format!("expect register or integer, found `{}`", piece))?),
+    };
+
+    match (operand, value) {
+        (Register, Value::Imm(_)) =>
+            Err(Box::from(format!("expect register, found `{}`", piece))),
+        (Immediate, Value::Reg(_)) =>
+            Err(Box::from(format!("expect integer, found `{}`", piece))),
+        _ =>
+            Ok(value),
+    }
+}
+
 #[test]
 fn tiny_sample() -> Result<(), Box<dyn Error>> {
     let sample = r"noop
@@ -158,7 +921,7 @@ fn tiny_sample() -> Result<(), Box<dyn Error>> {
         addx -5";
 
     let ins = read_puzzle(sample)?;
-    let measures = inspect_register(&ins);
+    let measures = inspect_register(&ins)?;
     assert_eq!(measures, vec![(1, 1), (4, 4), (6, -1)]);
 
     Ok(())
@@ -172,8 +935,277 @@ fn tailing_noop() -> Result<(), Box<dyn Error>> {
         noop";
 
     let ins = read_puzzle(sample)?;
-    let measures = inspect_register(&ins);
+    let measures = inspect_register(&ins)?;
     assert_eq!(measures, vec![(1, 1), (4, 4), (6, -1)]);
 
     Ok(())
 }
+
+#[test]
+fn draw_sample() -> Result<(), Box<dyn Error>> {
+    let sample = fs::read_to_string("sample.txt")?;
+    let lit = draw(&read_puzzle(&sample)?, &Crt::default())?;
+
+    let second_row = (0..40)
+        .map(|column| if lit.contains(&(1, column)) { '#' } else { '.' })
+        .collect::<String>();
+
+    assert_eq!(second_row, "###...###...###...###...###...###...###.");
+
+    Ok(())
+}
+
+#[test]
+fn timeline_queries() -> Result<(), Box<dyn Error>> {
+    let ins = read_puzzle("noop\naddx 3\naddx -5\nnoop")?;
+    let timeline = Timeline::new(&ins)?;
+
+    assert_eq!(timeline.at(0), None);
+    assert_eq!(timeline.at(1), Some(1));
+    assert_eq!(timeline.at(3), Some(1));
+    assert_eq!(timeline.at(4), Some(4));
+    assert_eq!(timeline.at(1000), Some(-1));
+
+    assert_eq!(timeline.during(2..7), vec![(2, 1), (4, 4), (6, -1)]);
+    assert_eq!(timeline.during(4..6), vec![(4, 4)]);
+    assert_eq!(timeline.during(5..5), vec![]);
+    assert_eq!(read_register(&ins, &[-1, 3, 5, 7])?, vec![1, 4, -1]);
+
+    Ok(())
+}
+
+#[test]
+fn countdown_loop() -> Result<(), Box<dyn Error>> {
+    // Walk the sprite right three times, then stop.
+    let sample = r"set a 3
+        # loop:
+        add x 2
+        add a -1
+        jnz a -2
+        set x 0";
+
+    let ins = read_puzzle(sample)?;
+    assert_eq!(ins[3], Ins { stmt: Stmt::Jnz(Value::Reg(1), -2), cycles: 2 });
+
+    let measures = inspect_register(&ins)?;
+    assert_eq!(measures, vec![(1, 1), (4, 3), (10, 5), (16, 7), (21, 0)]);
+
+    Ok(())
+}
+
+#[test]
+fn reject_bad_operands() {
+    assert!(read_puzzle("addx a").is_err());
+    assert!(read_puzzle("set 1 2").is_err());
+    assert!(read_puzzle("noop 1").is_err());
+    assert!(read_puzzle("jnz a").is_err());
+    assert!(read_puzzle("halt").is_err());
+}
+
+#[test]
+fn infinite_loop_stops() -> Result<(), Box<dyn Error>> {
+    let ins = read_puzzle("jmp 0")?;
+    assert_eq!(inspect_register(&ins)?, vec![(1, 1)]);
+    Ok(())
+}
+
+#[test]
+fn debug_countdown() -> Result<(), Box<dyn Error>> {
+    let sample = r"set a 3
+        add x 2
+        add a -1
+        jnz a -2
+        set x 0";
+
+    let ins = read_puzzle(sample)?;
+    let mut debugger = Debugger::new(&ins, Crt::default());
+
+    debugger.breakpoints.push(Breakpoint::Instruction(3));
+    assert_eq!(debugger.run(None), Stop::Breakpoint);
+    assert_eq!((debugger.cpu.cycle, debugger.cpu.registers), (6, [3, 2, 0, 0]));
+
+    // Cycle 9 is the second cycle of the second `add x 2`.
+    debugger.breakpoints = vec![Breakpoint::Cycle(9)];
+    assert_eq!(debugger.run(None), Stop::Breakpoint);
+    assert_eq!((debugger.cpu.pointer, debugger.cpu.cycle), (1, 8));
+
+    assert_eq!(debugger.run(Some(1)), Stop::Stepped);
+    assert_eq!(debugger.cpu.x(), 5);
+
+    debugger.breakpoints.clear();
+    debugger.watches.push(0);
+    assert_eq!(debugger.run(None), Stop::Watch(0, 5, 7));
+    assert_eq!(debugger.run(None), Stop::Watch(0, 7, 0));
+    assert_eq!(debugger.run(None), Stop::Halted);
+
+    // Twenty cycles ran, so the CRT drew the first twenty pixels.
+    assert_eq!(debugger.drawn.len(), 20);
+    assert_eq!(debugger.drawn[0], ((0, 0), true));
+    assert_eq!(debugger.drawn[19], ((0, 19), false));
+
+    Ok(())
+}
+
+#[test]
+fn debug_session() -> Result<(), Box<dyn Error>> {
+    let ins = read_puzzle("noop\naddx 3\naddx -5")?;
+    let mut debugger = Debugger::new(&ins, Crt::default());
+
+    let mut output = Vec::new();
+    debugger.session("step 2\ncrt\ncontinue\n".as_bytes(), &mut output)?;
+
+    let output = String::from_utf8(output)?;
+    let lines = output.lines().skip(3).collect::<Vec<&str>>();
+
+    assert_eq!(lines[0], "cycle 4 x=4 a=0 b=0 c=0 next 2: addx -5");
+    assert_eq!(lines[1], "###");
+    assert_eq!(lines[lines.len() - 1], "halted at cycle 6");
+
+    Ok(())
+}
+
+#[test]
+fn read_input_letters() -> Result<(), Box<dyn Error>> {
+    let puzzle = fs::read_to_string("input.txt")?;
+    let lit = draw(&read_puzzle(&puzzle)?, &Crt::default())?;
+
+    assert_eq!(read_letters(&lit, &Crt::default())?, "EZFCHJAB");
+
+    // Taller screens show the same letters, but there's more to them than letters.
+    let crt = Crt { height: 8, ..Crt::default() };
+    let lit = draw(&read_puzzle(&puzzle)?, &crt)?;
+    assert_eq!(read_letters(&lit, &crt), Err("expect CRT 6 pixels tall to read letters".into()));
+
+    Ok(())
+}
+
+#[test]
+fn read_unrecognized_letters() -> Result<(), Box<dyn Error>> {
+    let sample = fs::read_to_string("sample.txt")?;
+    let lit = draw(&read_puzzle(&sample)?, &Crt::default())?;
+
+    let message = read_letters(&lit, &Crt::default()).unwrap_err();
+
+    assert!(message.starts_with("unrecognized glyphs in \"????????\""));
+    assert!(message.contains("glyph 0:\n##..\n###.\n####\n####\n####\n####"));
+
+    let message = read_letters(&HashSet::new(), &Crt::default()).unwrap_err();
+    assert!(message.starts_with("unrecognized glyphs in \"????????\""));
+
+    Ok(())
+}
+
+#[test]
+fn render_small_crt() -> Result<(), Box<dyn Error>> {
+    // The sprite starts at `x = 1` and moves two to the right after the third cycle.
+    let ins = read_puzzle("noop\naddx 2\nnoop")?;
+    let crt = Crt { height: 2, width: 4, sprite_width: 2 };
+    let lit = draw(&ins, &crt)?;
+
+    assert_eq!(render(&lit, &crt, &Picture::ascii()), ".###\n...#\n");
+    assert_eq!(render(&lit, &crt, &Picture::Pbm), "P1\n4 2\n1 0 0 0\n1 1 1 0\n");
+    assert_eq!(render(&lit, &crt, &Picture::Pgm), "P2\n4 2\n255\n0 255 255 255\n0 0 0 255\n");
+
+    // Full rows are too long for one line.
+    let lit = draw(&ins, &Crt::default())?;
+
+    for picture in [Picture::Pbm, Picture::Pgm] {
+        let image = render(&lit, &Crt::default(), &picture);
+        assert!(image.lines().all(|line| line.len() <= 70), "{}", image);
+
+        let pixels = image.split_whitespace().count() - if picture == Picture::Pbm { 3 } else { 4 };
+        assert_eq!(pixels, 240);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_picture_options() -> Result<(), Box<dyn Error>> {
+    let args = ["--width", "8", "--sprite", "5", "--ascii", "--lit", "@"].map(String::from);
+    let options = read_options(&args)?;
+
+    assert_eq!(options.crt, Crt { height: 6, width: 8, sprite_width: 5 });
+    assert_eq!(
+        options.picture,
+        Some(Picture::Text { lit: "@".to_string(), dark: ".".to_string() })
+    );
+
+    assert!(read_options(&["--pbm", "--dark", "x"].map(String::from)).is_err());
+    assert!(read_options(&["--height", "0"].map(String::from)).is_err());
+    assert!(read_options(&["--height", "70000", "--width", "70000"].map(String::from)).is_err());
+    assert!(read_options(&["--width"].map(String::from)).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn trace_tiny_sample() -> Result<(), Box<dyn Error>> {
+    let ins = read_puzzle("noop\naddx 3\naddx -5")?;
+    let records = trace(&ins, &Crt::default())?;
+
+    assert_eq!(records.len(), 5);
+    assert_eq!(
+        records[2],
+        Record {
+            cycle: 3, pointer: 1, instruction: "addx 3".to_string(),
+            x_before: 1, x_after: 4,
+            pixel: Some(((0, 2), true)),
+            signal_strength: 3,
+        }
+    );
+
+    // The trace agrees with the register measurements.
+    let changes = records.iter()
+        .filter(|record| record.x_before != record.x_after)
+        .map(|record| (record.cycle + 1, record.x_after))
+        .collect::<Vec<(i32, i32)>>();
+    assert_eq!(changes, inspect_register(&ins)?[1..]);
+
+    let csv = trace_csv(&records);
+    assert_eq!(csv.lines().nth(1), Some("1,0,noop,1,1,0,0,true,1"));
+
+    let json = trace_json(&records);
+    assert!(json.contains(
+        "{\"cycle\": 5, \"pointer\": 2, \"instruction\": \"addx -5\", \"x_before\": 4, \
+         \"x_after\": -1, \"pixel\": {\"row\": 0, \"column\": 4, \"lit\": true}, \
+         \"signal_strength\": 20}"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn trace_past_the_frame() -> Result<(), Box<dyn Error>> {
+    let ins = read_puzzle("noop\nnoop\nnoop")?;
+    let records = trace(&ins, &Crt { height: 1, width: 2, sprite_width: 3 })?;
+
+    assert_eq!(records[2].pixel, None);
+    assert!(trace_csv(&records).ends_with("3,2,noop,1,1,,,,3\n"));
+
+    Ok(())
+}
+
+#[test]
+fn report_overflow() -> Result<(), Box<dyn Error>> {
+    let ins = read_puzzle("set a 2\nmul a a\njmp -1")?;
+
+    let message = "`mul a a` at instruction 1 overflows".to_string();
+    assert_eq!(inspect_register(&ins), Err(message.clone()));
+
+    let mut debugger = Debugger::new(&ins, Crt::default());
+    assert_eq!(debugger.run(None), Stop::Overflow(message));
+    assert_eq!(debugger.cpu.registers[1], 65536);
+
+    let ins = read_puzzle("addx 2147483647")?;
+    assert_eq!(
+        draw(&ins, &Crt::default()),
+        Err("`addx 2147483647` at instruction 0 overflows".to_string())
+    );
+
+    // Huge registers are fine as long as nothing overflows.
+    let ins = read_puzzle("addx 2147483646\nnoop")?;
+    assert_eq!(trace(&ins, &Crt::default())?[2].signal_strength, 3 * 2147483647);
+
+    Ok(())
+}
@@ -1,176 +1,1136 @@
-use std::collections::{BinaryHeap, VecDeque};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
 use std::env;
 use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::str::FromStr;
+use std::thread;
+
+use big::Big;
 
 type Worry = usize;
 type Label = usize;
 
+// What `Expr::eval` says when subtracting goes wrong, which `verify` needs to tell apart.
+const BELOW_ZERO: &str = "goes below zero";
+
+// The right-hand side of a monkey's operation, in terms of the `old` worry level.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expr {
+    Old,
+    Int(Worry),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token {
+    Old,
+    Int(Worry),
+    Infix(char),
+    Open,
+    Close,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 struct Throw {
     divisor: usize,
-    test: Box<dyn Fn(Worry) -> bool>,
     success: Label,
     failure: Label,
 }
 
-struct Monkey {
-    _label: Label,
-    items: VecDeque<Worry>,
-    inspect: Box<dyn Fn(Worry) -> Worry>,
+// Worry levels are `Worry` unless we're checking the simulation with arbitrary precision.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Monkey<L = Worry> {
+    label: Label,
+    items: VecDeque<L>,
+    inspect: Expr,
     inspections: usize,
     throw: Throw,
+    // How many items this monkey has thrown to each other monkey.
+    throws: BTreeMap<Label, usize>,
+}
+
+// The monkeys at the end of a round. Counts add up from the start of the simulation.
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    round: usize,
+    items: Vec<usize>,
+    inspections: Vec<usize>,
+    // `throws[from][to]` is the number of items monkey `from` has thrown to monkey `to`.
+    throws: Vec<Vec<usize>>,
+}
+
+#[derive(Debug)]
+struct Simulation {
+    monkeys: Vec<Monkey>,
+    snapshots: Vec<Snapshot>,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = env::args().collect::<Vec<String>>();
-    let puzzle = fs::read_to_string(&args[1])?;
 
     if args.len() < 2 {
-        return Err(Box::from("usage: cargo run -- puzzle-input"));
+        return Err(Box::from(
+            "usage: cargo run -- puzzle-input [--top k] [--csv (one | two) file] \
+             [--dot (one | two) file] [--threads n] [--verify rounds | --extrapolate rounds]"
+        ));
     }
 
-    let (largest, second_largest) = simulate(&puzzle, 20, false);
-    println!("part one: {} * {} = {}", largest, second_largest, largest * second_largest);
+    let puzzle = fs::read_to_string(&args[1])?;
+    let monkeys = read_monkeys(&puzzle)?;
+
+    let mut top = None;
+    let mut csv: Option<(String, String)> = None;
+    let mut dot: Option<(String, String)> = None;
+    let mut threads = None;
+
+    match &args[2..] {
+        [] => (),
+        [flag, rounds] if flag == "--verify" => {
+            let rounds = rounds.parse::<usize>()?;
+
+            for (part, ludicrous_speed) in [("part one", false), ("part two", true)] {
+                match verify(&monkeys, rounds, ludicrous_speed)? {
+                    None => println!(
+                        "{}: item modulus agrees with exact worry for {} rounds", part, rounds
+                    ),
+                    Some(round) => println!(
+                        "{}: item modulus disagrees at round {}", part, round
+                    ),
+                }
+            }
+
+            return Ok(());
+        }
+        [flag, rounds] if flag == "--extrapolate" => {
+            let rounds = rounds.parse::<u64>()?;
+            let (largest, second_largest) = extrapolate(&monkeys, rounds)?;
+            println!(
+                "{} rounds: {} * {} = {}",
+                rounds, largest, second_largest, largest as u128 * second_largest as u128
+            );
+
+            return Ok(());
+        }
+        options => {
+            let mut options = options.iter();
+
+            while let Some(flag) = options.next() {
+                let mut value = || options.next().ok_or(format!("expect value after `{}`", flag));
+
+                match flag.as_str() {
+                    "--top" => top = Some(value()?.parse::<usize>()?),
+                    "--csv" => csv = Some((value()?.clone(), value()?.clone())),
+                    "--dot" => dot = Some((value()?.clone(), value()?.clone())),
+                    "--threads" => threads = Some(value()?.parse::<usize>()?),
+                    oops => return Err(Box::from(format!("unexpected option `{}`", oops))),
+                }
+            }
+        }
+    }
+
+    for (flag, option) in [("--csv", &csv), ("--dot", &dot)] {
+        let bad = option.as_ref().filter(|(which, _)| which != "one" && which != "two");
+
+        if let Some((which, _)) = bad {
+            let message = format!("expect `one` or `two` after `{}`, found `{}`", flag, which);
+            return Err(Box::from(message));
+        }
+    }
 
-    let (largest, second_largest) = simulate(&puzzle, 10000, true);
-    println!("part two: {} * {} = {}", largest, second_largest, largest * second_largest);
+    for (part, rounds, ludicrous_speed) in [("one", 20, false), ("two", 10000, true)] {
+        let recording = csv.as_ref().filter(|(which, _)| which == part);
+
+        // Snapshots need every round in order, and worry levels that can't be reduced need the
+        // exact simulation, so those always run on one thread.
+        let parallel = ludicrous_speed && recording.is_none() && require_modular(&monkeys).is_ok();
+
+        let simulation = match threads {
+            Some(threads) if parallel => {
+                simulate_parallel(&monkeys, rounds, threads)?
+            }
+            _ => simulate(&monkeys, rounds, ludicrous_speed, recording.is_some())?,
+        };
+
+        let (largest, second_largest) = simulation.monkey_business();
+        println!(
+            "part {}: {} * {} = {}",
+            part, largest, second_largest, largest * second_largest
+        );
+
+        if let Some(top) = top {
+            for (label, inspections) in simulation.busiest(top) {
+                println!("  monkey {}: {} inspections", label, inspections);
+            }
+        }
+
+        if let Some((_, file)) = recording {
+            fs::write(file, simulation.csv())?;
+        }
+
+        if let Some((_, file)) = dot.as_ref().filter(|(which, _)| which == part) {
+            fs::write(file, simulation.dot())?;
+        }
+    }
 
     Ok(())
 }
 
-fn simulate(puzzle: &str, rounds: usize, ludicrous_speed: bool) -> (usize, usize) {
-    // TODO: Can't clone the monkeys because trait objects are not `Clone`. Have to read the input
-    // twice.
+// A worry level that reports overflow instead of wrapping or panicking.
+trait Level: Clone + fmt::Display + Sized {
+    fn from_worry(worry: Worry) -> Self;
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_sub(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+    fn checked_div(&self, other: &Self) -> Option<Self>;
+    fn rem(&self, modulus: Worry) -> Worry;
+}
+
+impl Level for Worry {
+    fn from_worry(worry: Worry) -> Self { worry }
+    fn checked_add(&self, other: &Self) -> Option<Self> { Worry::checked_add(*self, *other) }
+    fn checked_sub(&self, other: &Self) -> Option<Self> { Worry::checked_sub(*self, *other) }
+    fn checked_mul(&self, other: &Self) -> Option<Self> { Worry::checked_mul(*self, *other) }
+    fn checked_div(&self, other: &Self) -> Option<Self> { Worry::checked_div(*self, *other) }
+    fn rem(&self, modulus: Worry) -> Worry { self % modulus }
+}
+
+impl Expr {
+    fn eval<L: Level>(&self, old: &L) -> Result<L, String> {
+        let (left, right) = match self {
+            Expr::Old => return Ok(old.clone()),
+            Expr::Int(int) => return Ok(L::from_worry(*int)),
+            Expr::Add(left, right) | Expr::Sub(left, right) |
+            Expr::Mul(left, right) | Expr::Div(left, right) =>
+                (left.eval(old)?, right.eval(old)?),
+        };
 
-    let mut monkeys = read_monkeys(&puzzle);
+        let (value, problem) = match self {
+            Expr::Add(..) => (left.checked_add(&right), "overflows"),
+            Expr::Sub(..) => (left.checked_sub(&right), BELOW_ZERO),
+            Expr::Mul(..) => (left.checked_mul(&right), "overflows"),
+            _ => (left.checked_div(&right), "divides by zero"),
+        };
+
+        value.ok_or(format!("`{}` {} when old = {}", self, problem, old))
+    }
 
-    for _ in 1..=rounds {
-        round(&mut monkeys, ludicrous_speed);
+    // Whether the result modulo any number only depends on `old` modulo that number, which is what
+    // lets us keep worry levels down in part two. Subtracting and dividing don't work that way.
+    fn modular(&self) -> bool {
+        match self {
+            Expr::Old | Expr::Int(_) => true,
+            Expr::Add(left, right) | Expr::Mul(left, right) => left.modular() && right.modular(),
+            Expr::Sub(..) | Expr::Div(..) => false,
+        }
     }
 
-    let mut inspections = monkeys.iter()
-        .map(|monkey| monkey.inspections)
-        .collect::<BinaryHeap<usize>>();
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Add(..) | Expr::Sub(..) => 1,
+            Expr::Mul(..) | Expr::Div(..) => 2,
+            Expr::Old | Expr::Int(_) => 3,
+        }
+    }
+}
 
-    let largest = inspections.pop().unwrap();
-    let second_largest = inspections.pop().unwrap();
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (infix, left, right) = match self {
+            Expr::Old => return write!(f, "old"),
+            Expr::Int(int) => return write!(f, "{}", int),
+            Expr::Add(left, right) => ('+', left, right),
+            Expr::Sub(left, right) => ('-', left, right),
+            Expr::Mul(left, right) => ('*', left, right),
+            Expr::Div(left, right) => ('/', left, right),
+        };
+
+        // Operators associate to the left, so a right operand with the same precedence needs
+        // parentheses to read back as the same tree.
+        if left.precedence() < self.precedence() {
+            write!(f, "({})", left)?;
+        } else {
+            write!(f, "{}", left)?;
+        }
+
+        write!(f, " {} ", infix)?;
+
+        if right.precedence() <= self.precedence() {
+            write!(f, "({})", right)
+        } else {
+            write!(f, "{}", right)
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Recursive descent, one function per level of precedence:
+        //
+        //     sum     := product (('+' | '-') product)*
+        //     product := atom (('*' | '/') atom)*
+        //     atom    := 'old' | integer | '(' sum ')'
+
+        let tokens = read_tokens(s)?;
+        let mut tokens = tokens.iter().copied().peekable();
+
+        type Tokens<'a> = std::iter::Peekable<std::iter::Copied<std::slice::Iter<'a, Token>>>;
+
+        fn sum(tokens: &mut Tokens) -> Result<Expr, String> {
+            let mut left = product(tokens)?;
+
+            while let Some(Token::Infix(infix @ ('+' | '-'))) = tokens.peek().copied() {
+                tokens.next();
+                let right = Box::new(product(tokens)?);
+                left = match infix {
+                    '+' => Expr::Add(Box::new(left), right),
+                    _ => Expr::Sub(Box::new(left), right),
+                };
+            }
+
+            Ok(left)
+        }
+
+        fn product(tokens: &mut Tokens) -> Result<Expr, String> {
+            let mut left = atom(tokens)?;
+
+            while let Some(Token::Infix(infix @ ('*' | '/'))) = tokens.peek().copied() {
+                tokens.next();
+                let right = Box::new(atom(tokens)?);
+                left = match infix {
+                    '*' => Expr::Mul(Box::new(left), right),
+                    _ => Expr::Div(Box::new(left), right),
+                };
+            }
+
+            Ok(left)
+        }
+
+        fn atom(tokens: &mut Tokens) -> Result<Expr, String> {
+            match tokens.next() {
+                Some(Token::Old) => Ok(Expr::Old),
+                Some(Token::Int(int)) => Ok(Expr::Int(int)),
+                Some(Token::Open) => {
+                    let inside = sum(tokens)?;
+                    match tokens.next() {
+                        Some(Token::Close) => Ok(inside),
+                        Some(token) => Err(format!("expect ')', found {:?}", token)),
+                        None => Err("expect ')', found end of operation".to_string()),
+                    }
+                }
+                Some(token) => Err(format!("expect `old`, integer or '(', found {:?}", token)),
+                None => Err("expect `old`, integer or '(', found end of operation".to_string()),
+            }
+        }
+
+        let expr = sum(&mut tokens)?;
+
+        match tokens.next() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {:?} after operation", token)),
+        }
+    }
+}
+
+fn read_tokens(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut symbols = s.char_indices().peekable();
+
+    while let Some((start, symbol)) = symbols.next() {
+        match symbol {
+            '+' | '-' | '*' | '/' => tokens.push(Token::Infix(symbol)),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            _ if symbol.is_whitespace() => (),
+            _ if symbol.is_ascii_alphanumeric() => {
+                let mut end = start + symbol.len_utf8();
+
+                while let Some(&(next, symbol)) = symbols.peek() {
+                    if !symbol.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    end = next + symbol.len_utf8();
+                    symbols.next();
+                }
+
+                let word = &s[start..end];
+
+                tokens.push(match word {
+                    "old" => Token::Old,
+                    _ => Token::Int(
+                        word.parse::<Worry>()
+                            .map_err(|_| format!("unexpected `{}` in operation", word))?
+                    ),
+                });
+            }
+            oops => return Err(format!("unexpected '{}' in operation", oops)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+impl Throw {
+    fn test<L: Level>(&self, worry: &L) -> bool {
+        worry.rem(self.divisor) == 0
+    }
+}
+
+impl<L: fmt::Display> fmt::Display for Monkey<L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The same notes `read_monkey` reads, holding the items the monkey has right now.
+        let items = self.items.iter()
+            .map(|item| format!(" {}", item))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        writeln!(f, "Monkey {}:", self.label)?;
+        writeln!(f, "  Starting items:{}", items)?;
+        writeln!(f, "  Operation: new = {}", self.inspect)?;
+        writeln!(f, "  Test: divisible by {}", self.throw.divisor)?;
+        writeln!(f, "    If true: throw to monkey {}", self.throw.success)?;
+        write!(f, "    If false: throw to monkey {}", self.throw.failure)
+    }
+}
+
+fn simulate(
+    monkeys: &[Monkey], rounds: usize, ludicrous_speed: bool, snapshots: bool
+) -> Result<Simulation, String> {
+    let item_modulus = reduction(monkeys, ludicrous_speed)?;
+    let mut simulation = Simulation { monkeys: monkeys.to_vec(), snapshots: Vec::new() };
+
+    for number in 1..=rounds {
+        round(&mut simulation.monkeys, ludicrous_speed, item_modulus)
+            .map_err(|message| match require_modular(monkeys) {
+                Err(reason) if ludicrous_speed => format!("{} ({})", message, reason),
+                _ => message,
+            })?;
+
+        if snapshots {
+            let snapshot = Snapshot::new(number, &simulation.monkeys);
+            simulation.snapshots.push(snapshot);
+        }
+    }
+
+    Ok(simulation)
+}
+
+impl Snapshot {
+    fn new(round: usize, monkeys: &[Monkey]) -> Self {
+        let throws = monkeys.iter()
+            .map(|monkey| {
+                (0..monkeys.len())
+                    .map(|to| monkey.throws.get(&to).copied().unwrap_or(0))
+                    .collect()
+            })
+            .collect();
+
+        Snapshot {
+            round,
+            items: monkeys.iter().map(|monkey| monkey.items.len()).collect(),
+            inspections: monkeys.iter().map(|monkey| monkey.inspections).collect(),
+            throws,
+        }
+    }
+}
+
+impl Simulation {
+    fn busiest(&self, k: usize) -> Vec<(Label, usize)> {
+        // The `k` monkeys with the most inspections, busiest first. Ties go to the lower label.
+        let mut busiest = self.monkeys.iter()
+            .map(|monkey| (monkey.label, monkey.inspections))
+            .collect::<Vec<(Label, usize)>>();
+
+        busiest.sort_by_key(|&(label, inspections)| (std::cmp::Reverse(inspections), label));
+        busiest.truncate(k);
+        busiest
+    }
+
+    fn monkey_business(&self) -> (usize, usize) {
+        // There are always at least two monkeys because monkeys don't throw to themselves.
+        match self.busiest(2)[..] {
+            [(_, largest), (_, second_largest)] => (largest, second_largest),
+            _ => unreachable!(),
+        }
+    }
+
+    fn dot(&self) -> String {
+        // Graphviz source for who throws to whom. Edges get thicker the more items went along them.
+        let most = self.monkeys.iter()
+            .flat_map(|monkey| monkey.throws.values())
+            .max()
+            .copied()
+            .unwrap_or(0)
+            .max(1);
+
+        let mut dot = "digraph monkeys {\n    node [shape=box];\n".to_string();
+
+        for monkey in &self.monkeys {
+            dot.push_str(&format!(
+                "    {} [label=\"Monkey {}\\nnew = {}\\ndivisible by {}\"];\n",
+                monkey.label, monkey.label, monkey.inspect, monkey.throw.divisor
+            ));
+        }
+
+        for monkey in &self.monkeys {
+            // Throws are only counted per target, so a monkey that throws to the same monkey
+            // either way gets a single edge.
+            let edges = if monkey.throw.success == monkey.throw.failure {
+                vec![("either", monkey.throw.success)]
+            } else {
+                vec![("true", monkey.throw.success), ("false", monkey.throw.failure)]
+            };
+
+            for (test, target) in edges {
+                let throws = monkey.throws.get(&target).copied().unwrap_or(0);
+                let width = 1.0 + 4.0 * throws as f64 / most as f64;
+
+                dot.push_str(&format!(
+                    "    {} -> {} [label=\"{}: {}\", weight={}, penwidth={:.2}];\n",
+                    monkey.label, target, test, throws, throws, width
+                ));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn csv(&self) -> String {
+        // One row per monkey per round, with a column for the throws to each monkey.
+        let monkeys = self.monkeys.len();
+
+        let mut csv = "round,monkey,items,inspections".to_string();
+        for to in 0..monkeys {
+            csv.push_str(&format!(",throws_to_{}", to));
+        }
+        csv.push('\n');
+
+        for snapshot in &self.snapshots {
+            for monkey in 0..monkeys {
+                csv.push_str(&format!(
+                    "{},{},{},{}",
+                    snapshot.round, monkey, snapshot.items[monkey], snapshot.inspections[monkey]
+                ));
+                for throws in &snapshot.throws[monkey] {
+                    csv.push_str(&format!(",{}", throws));
+                }
+                csv.push('\n');
+            }
+        }
+
+        csv
+    }
+}
 
-    (largest, second_largest)
+fn item_modulus(monkeys: &[Monkey]) -> Result<Worry, String> {
+    // Every test still passes or fails the same way if worry levels are taken modulo the product
+    // of the divisors.
+    monkeys.iter()
+        .try_fold(1, |modulus: Worry, monkey| modulus.checked_mul(monkey.throw.divisor))
+        .ok_or("product of divisors overflows".to_string())
 }
 
-fn round(monkeys: &mut Vec<Monkey>, ludicrous_speed: bool) {
-    let item_modulus = monkeys.iter()
-        .map(|monkey| monkey.throw.divisor)
-        .product::<usize>();
+fn require_modular(monkeys: &[Monkey]) -> Result<(), String> {
+    match monkeys.iter().find(|monkey| !monkey.inspect.modular()) {
+        Some(monkey) => Err(format!(
+            "monkey {} does `{}`, which doesn't work modulo the divisors",
+            monkey.label, monkey.inspect
+        )),
+        None => Ok(()),
+    }
+}
+
+fn reduction(monkeys: &[Monkey], ludicrous_speed: bool) -> Result<Option<Worry>, String> {
+    // Dividing by three throws away what the item modulus keeps track of just like `-` and `/`
+    // do, so part one and notes with those operations run with exact worry levels instead.
+    if !ludicrous_speed || require_modular(monkeys).is_err() {
+        Ok(None)
+    } else {
+        item_modulus(monkeys).map(Some)
+    }
+}
+
+fn verify(
+    monkeys: &[Monkey], rounds: usize, ludicrous_speed: bool
+) -> Result<Option<usize>, String> {
+    // Run the simulation with the item modulus next to one with exact worry levels, and find the
+    // first round where the monkeys' inspections disagree.
+
+    let item_modulus = item_modulus(monkeys)?;
+
+    let mut modular = monkeys.to_vec();
+    let mut exact = monkeys.iter().map(Monkey::exact).collect::<Vec<Monkey<Big>>>();
+
+    for number in 1..=rounds {
+        // Worry that's been reduced can go below zero when the exact worry doesn't, and that's
+        // the item modulus getting it wrong too. Overflowing is just `Worry` being too small.
+        match round(&mut modular, ludicrous_speed, Some(item_modulus)) {
+            Err(message) if message.contains(BELOW_ZERO) => return Ok(Some(number)),
+            Err(message) => {
+                return Err(format!("modular run fails at round {}: {}", number, message));
+            }
+            Ok(()) => (),
+        }
+
+        round(&mut exact, ludicrous_speed, None)?;
+
+        let disagree = modular.iter()
+            .zip(exact.iter())
+            .any(|(modular, exact)| modular.inspections != exact.inspections);
+
+        if disagree {
+            return Ok(Some(number));
+        }
+    }
+
+    Ok(None)
+}
+
+impl Monkey {
+    fn exact(&self) -> Monkey<Big> {
+        Monkey {
+            label: self.label,
+            items: self.items.iter().map(|&item| Big::from_worry(item)).collect(),
+            inspect: self.inspect.clone(),
+            inspections: self.inspections,
+            throw: self.throw.clone(),
+            throws: self.throws.clone(),
+        }
+    }
+
+    fn pass(&self, worry: Worry, item_modulus: Worry) -> Result<(Worry, Label), String> {
+        // Inspect an item in part two and decide where it goes.
+        let worry = self.inspect.eval(&worry)
+            .map_err(|message| format!("monkey {}: {}", self.label, message))?
+            % item_modulus;
+
+        let target = if self.throw.test(&worry) {
+            self.throw.success
+        } else {
+            self.throw.failure
+        };
+
+        Ok((worry, target))
+    }
+}
+
+fn round<L: Level>(
+    monkeys: &mut [Monkey<L>], ludicrous_speed: bool, item_modulus: Option<Worry>
+) -> Result<(), String> {
+    let three = L::from_worry(3);
 
     for i in 0..monkeys.len() {
-        while monkeys[i].items.len() > 0 {
+        while !monkeys[i].items.is_empty() {
             let monkey = &mut monkeys[i];
 
             let item = monkey.items.pop_front().unwrap();
 
-            let mut new_item = (monkey.inspect)(item);
+            let mut new_item = monkey.inspect.eval(&item)
+                .map_err(|message| format!("monkey {}: {}", monkey.label, message))?;
 
             if !ludicrous_speed {
-                new_item = new_item / 3;
+                // Can't fail, three isn't zero.
+                new_item = new_item.checked_div(&three).unwrap();
             }
 
-            new_item = new_item % item_modulus;
+            if let Some(item_modulus) = item_modulus {
+                new_item = L::from_worry(new_item.rem(item_modulus));
+            }
 
             monkey.inspections += 1;
 
-            let (success, failure) = (monkey.throw.success, monkey.throw.failure);
-            if (monkey.throw.test)(new_item) {
-                monkeys[success].items.push_back(new_item);
+            let target = if monkey.throw.test(&new_item) {
+                monkey.throw.success
             } else {
-                monkeys[failure].items.push_back(new_item);
+                monkey.throw.failure
+            };
+
+            *monkey.throws.entry(target).or_insert(0) += 1;
+            monkeys[target].items.push_back(new_item);
+        }
+    }
+
+    Ok(())
+}
+
+fn simulate_parallel(
+    monkeys: &[Monkey], rounds: usize, threads: usize
+) -> Result<Simulation, String> {
+    // Part two, with the items split up between threads. Each item goes through all the rounds on
+    // its own and the counts are added up at the end. Items end up with the same monkeys as in
+    // `simulate`, but not necessarily in the same order.
+
+    require_modular(monkeys)?;
+    let item_modulus = item_modulus(monkeys)?;
+
+    let items = monkeys.iter()
+        .flat_map(|monkey| monkey.items.iter().map(|&item| (monkey.label, item)))
+        .collect::<Vec<(Label, Worry)>>();
+
+    let chunk = items.len().div_ceil(threads.max(1)).max(1);
+
+    let tallies = thread::scope(|scope| {
+        let handles = items.chunks(chunk)
+            .map(|items| scope.spawn(move || walk(monkeys, items, rounds, item_modulus)))
+            .collect::<Vec<_>>();
+
+        handles.into_iter()
+            .map(|handle| handle.join().expect("walking items doesn't panic"))
+            .collect::<Result<Vec<Vec<Monkey>>, String>>()
+    })?;
+
+    let mut simulation = Simulation { monkeys: monkeys.to_vec(), snapshots: Vec::new() };
+
+    for monkey in &mut simulation.monkeys {
+        monkey.items.clear();
+    }
+
+    for tally in tallies {
+        for (monkey, counted) in simulation.monkeys.iter_mut().zip(tally) {
+            monkey.items.extend(counted.items);
+            monkey.inspections += counted.inspections;
+
+            for (target, throws) in counted.throws {
+                *monkey.throws.entry(target).or_insert(0) += throws;
+            }
+        }
+    }
+
+    Ok(simulation)
+}
+
+fn walk(
+    monkeys: &[Monkey], items: &[(Label, Worry)], rounds: usize, item_modulus: Worry
+) -> Result<Vec<Monkey>, String> {
+    // The monkeys' counts for just these items, and where the items end up.
+    let mut tally = monkeys.iter()
+        .map(|monkey| Monkey {
+            items: VecDeque::new(),
+            inspections: 0,
+            throws: BTreeMap::new(),
+            ..monkey.clone()
+        })
+        .collect::<Vec<Monkey>>();
+
+    for &(mut holder, mut worry) in items {
+        for _ in 0..rounds {
+            loop {
+                let target;
+                (worry, target) = monkeys[holder].pass(worry, item_modulus)?;
+
+                tally[holder].inspections += 1;
+                *tally[holder].throws.entry(target).or_insert(0) += 1;
+
+                let later = target > holder;
+                holder = target;
+
+                if !later {
+                    break;
+                }
             }
         }
+
+        tally[holder].items.push_back(worry);
     }
+
+    Ok(tally)
 }
 
-fn read_monkeys(notes: &str) -> Vec<Monkey> {
-    notes.split("\n\n")
-        .flat_map(read_monkey) // Danger! Skips garbage.
-        .collect::<Vec<Monkey>>()
+// The monkeys that inspect one item in part two, round by round. Without the division by three
+// an item's worry level doesn't depend on any other item, so it can be followed on its own.
+#[derive(Debug, PartialEq)]
+struct Trajectory {
+    visits: Vec<Label>,
+    // Round `r` is made of the visits from `rounds[r]` up to the start of the next round.
+    rounds: Vec<usize>,
+    // The item is back where it was at the start of round `start`, so rounds `start..` repeat
+    // every `period` rounds.
+    start: usize,
+    period: usize,
+}
+
+impl Trajectory {
+    fn round(&self, round: usize) -> &[Label] {
+        let end = self.rounds.get(round + 1).copied().unwrap_or(self.visits.len());
+        &self.visits[self.rounds[round]..end]
+    }
+
+    fn inspections(&self, rounds: u64, monkeys: usize) -> Vec<u64> {
+        // How many times each monkey inspects the item in the first `rounds` rounds.
+        let mut inspections = vec![0; monkeys];
+
+        let mut count = |range: std::ops::Range<usize>, times: u64| {
+            for round in range {
+                for &monkey in self.round(round) {
+                    inspections[monkey] += times;
+                }
+            }
+        };
+
+        let (start, period) = (self.start as u64, self.period as u64);
+
+        if rounds <= start {
+            count(0..rounds as usize, 1);
+        } else {
+            let (cycles, remainder) = ((rounds - start) / period, (rounds - start) % period);
+            count(0..self.start, 1);
+            count(self.start..self.start + self.period, cycles);
+            count(self.start..self.start + remainder as usize, 1);
+        }
+
+        inspections
+    }
 }
 
-fn read_monkey(notes: &str) -> Option<Monkey> {
-    let mut lines = notes.lines()
-        .map(|line| line.trim());
+fn follow(
+    monkeys: &[Monkey], mut holder: Label, mut worry: Worry, item_modulus: Worry
+) -> Result<Trajectory, String> {
+    let mut visits = Vec::new();
+    let mut rounds = Vec::new();
+    let mut seen = HashMap::new();
+
+    worry %= item_modulus;
+
+    loop {
+        if let Some(&start) = seen.get(&(holder, worry)) {
+            let period = rounds.len() - start;
+            return Ok(Trajectory { visits, rounds, start, period });
+        }
+
+        seen.insert((holder, worry), rounds.len());
+        rounds.push(visits.len());
+
+        // Monkeys take their turns in order, so an item thrown to a later monkey is inspected
+        // again this round, and one thrown to an earlier monkey waits for the next round.
+        loop {
+            let target;
+            (worry, target) = monkeys[holder].pass(worry, item_modulus)?;
+
+            visits.push(holder);
 
-    let label = lines.next()?
-        .strip_prefix("Monkey ")?
-        .strip_suffix(":")?
-        .parse::<usize>().ok()?;
+            let later = target > holder;
+            holder = target;
 
-    let items = lines.next()?
-        .strip_prefix("Starting items: ")?
-        .split(", ")
-        .flat_map(|item| item.parse::<usize>().ok()) // Danger! Skips garbage.
-        .collect::<VecDeque<usize>>();
+            if !later {
+                break;
+            }
+        }
+    }
+}
 
-    let mut expr = lines.next()?
-        .strip_prefix("Operation: new = old ")?
-        .split_ascii_whitespace()
-        .take(2);
+fn extrapolate(monkeys: &[Monkey], rounds: u64) -> Result<(u64, u64), String> {
+    // Part two for any number of rounds, by following each item until its trajectory repeats.
+    require_modular(monkeys)?;
+    let item_modulus = item_modulus(monkeys)?;
+    let mut inspections = vec![0; monkeys.len()];
 
-    let inspect = if let [Some(infix), Some(right)] = [expr.next(), expr.next()] {
-        read_operation(infix, right)
+    for monkey in monkeys {
+        for &item in &monkey.items {
+            let trajectory = follow(monkeys, monkey.label, item, item_modulus)?;
+
+            let counts = trajectory.inspections(rounds, monkeys.len());
+
+            for (total, count) in inspections.iter_mut().zip(counts) {
+                *total += count;
+            }
+        }
+    }
+
+    let mut inspections = inspections.into_iter().collect::<BinaryHeap<u64>>();
+
+    // There are always at least two monkeys because monkeys don't throw to themselves.
+    let largest = inspections.pop().unwrap();
+    let second_largest = inspections.pop().unwrap();
+
+    Ok((largest, second_largest))
+}
+
+fn read_monkeys(notes: &str) -> Result<Vec<Monkey>, String> {
+    // Monkeys are separated by blank lines. Keep line numbers around for error messages.
+    let lines = (1..).zip(notes.lines()).collect::<Vec<(usize, &str)>>();
+
+    let monkeys = lines.split(|(_, line)| line.trim().is_empty())
+        .filter(|note| !note.is_empty())
+        .map(read_note)
+        .collect::<Result<Vec<Monkey>, String>>()?;
+
+    if monkeys.is_empty() {
+        return Err("expect at least one monkey".to_string());
+    }
+
+    for (expected, monkey) in monkeys.iter().enumerate() {
+        if monkey.label != expected {
+            return Err(format!("expect monkey {}, found monkey {}", expected, monkey.label));
+        }
+
+        for target in [monkey.throw.success, monkey.throw.failure] {
+            if target >= monkeys.len() {
+                return Err(format!(
+                    "monkey {} throws to monkey {}, which doesn't exist", monkey.label, target
+                ));
+            }
+
+            // The monkey would keep catching its own items and never finish its turn.
+            if target == monkey.label {
+                return Err(format!("monkey {} throws to itself", monkey.label));
+            }
+        }
+    }
+
+    Ok(monkeys)
+}
+
+#[cfg(test)]
+fn read_monkey(notes: &str) -> Result<Monkey, String> {
+    read_note(&(1..).zip(notes.lines()).collect::<Vec<(usize, &str)>>())
+}
+
+fn read_note(note: &[(usize, &str)]) -> Result<Monkey, String> {
+    let mut lines = note.iter()
+        .map(|&(number, line)| (number, line.trim()));
+
+    let end = note.last().map(|&(number, _)| number).unwrap_or(0);
+
+    // Strip the expected prefix from the next line, and parse what's left with `read`.
+    let mut field = |prefix: &str, suffix: &str| -> Result<(usize, String), String> {
+        let (number, line) = lines.next()
+            .ok_or(format!("line {}: expect `{}` after this line", end, prefix))?;
+
+        let value = line.strip_prefix(prefix)
+            .and_then(|line| line.strip_suffix(suffix))
+            .ok_or(format!("line {}: expect `{}...{}`, found `{}`", number, prefix, suffix, line))?;
+
+        Ok((number, value.trim().to_string()))
+    };
+
+    let int = |(number, value): (usize, String), what: &str| {
+        value.parse::<usize>()
+            .map_err(|_| format!("line {}: expect {}, found `{}`", number, what, value))
+    };
+
+    let label = int(field("Monkey ", ":")?, "monkey label")?;
+
+    let (number, items) = field("Starting items:", "")?;
+    let items = if items.is_empty() {
+        VecDeque::new()
     } else {
-        None
-    }?;
+        items.split(',')
+            .map(|item| int((number, item.trim().to_string()), "worry level"))
+            .collect::<Result<VecDeque<Worry>, String>>()?
+    };
 
-    let divisor = lines.next()?
-        .strip_prefix("Test: divisible by ")?
-        .parse::<usize>().ok()?;
+    let (number, operation) = field("Operation: new = ", "")?;
+    let inspect = operation.parse::<Expr>()
+        .map_err(|message| format!("line {}: {}", number, message))?;
 
-    let success = lines.next()?
-        .strip_prefix("If true: throw to monkey ")?
-        .parse::<usize>().ok()?;
+    let (number, divisor) = field("Test: divisible by ", "")?;
+    let divisor = int((number, divisor), "divisor")?;
 
-    let failure = lines.next()?
-        .strip_prefix("If false: throw to monkey ")?
-        .parse::<usize>().ok()?;
+    if divisor == 0 {
+        return Err(format!("line {}: expect divisor to be positive", number));
+    }
+
+    let success = int(field("If true: throw to monkey ", "")?, "monkey label")?;
+    let failure = int(field("If false: throw to monkey ", "")?, "monkey label")?;
+
+    if let Some((number, line)) = lines.next() {
+        return Err(format!("line {}: unexpected `{}` after monkey {}", number, line, label));
+    }
 
-    Some(Monkey {
-        _label: label,
+    Ok(Monkey {
+        label,
         items,
         inspect,
         inspections: 0,
         throw: Throw {
             divisor,
-            test: Box::new(move |old| old % divisor == 0),
             success,
             failure
-        }
+        },
+        throws: BTreeMap::new(),
     })
 }
 
-fn read_operation(infix: &str, right: &str) -> Option<Box<dyn Fn(Worry) -> Worry>> {
-        // Assumptions:
-        // - The left operand is always `old`.
-        // - The operand is addition or multiplication.
+mod big {
+    use std::cmp::Ordering;
+    use std::fmt;
 
-        let operands = match right {
-            "old" => None,
-            _ => right.parse::<usize>().ok(),
-        };
+    use super::{Level, Worry};
 
-        let operation: Option<Box<dyn Fn(Worry) -> Worry>> = match operands {
-            None => match infix {
-                "+" => Some(Box::new(|old| old + old)),
-                "*" => Some(Box::new(|old| old * old)),
-                _ => None,
+    // An arbitrary precision unsigned integer, least significant digit first, in base 2^32. There
+    // are never leading zero digits, so zero has no digits at all.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct Big(Vec<u32>);
+
+    impl Big {
+        fn trim(mut digits: Vec<u32>) -> Big {
+            while digits.last() == Some(&0) {
+                digits.pop();
             }
-            Some(right) => match infix {
-                "+" => Some(Box::new(move |old| old + right)),
-                "*" => Some(Box::new(move |old| old * right)),
-                _ => None,
+            Big(digits)
+        }
+
+        fn bits(&self) -> usize {
+            match self.0.last() {
+                None => 0,
+                Some(top) => 32 * self.0.len() - top.leading_zeros() as usize,
             }
-        };
+        }
+
+        fn bit(&self, bit: usize) -> bool {
+            self.0.get(bit / 32).map(|digit| digit >> (bit % 32) & 1 == 1).unwrap_or(false)
+        }
+
+        fn div_rem_small(&self, divisor: u32) -> (Big, u32) {
+            let mut quotient = vec![0; self.0.len()];
+            let mut remainder: u64 = 0;
+
+            for (place, &digit) in self.0.iter().enumerate().rev() {
+                let dividend = remainder << 32 | digit as u64;
+                quotient[place] = (dividend / divisor as u64) as u32;
+                remainder = dividend % divisor as u64;
+            }
+
+            (Big::trim(quotient), remainder as u32)
+        }
+    }
+
+    impl Ord for Big {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.0.len().cmp(&other.0.len())
+                .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+        }
+    }
+
+    impl PartialOrd for Big {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
 
-        operation
+    impl Level for Big {
+        fn from_worry(worry: Worry) -> Self {
+            let worry = worry as u64;
+            Big::trim(vec![worry as u32, (worry >> 32) as u32])
+        }
+
+        fn checked_add(&self, other: &Self) -> Option<Self> {
+            let mut sum = Vec::new();
+            let mut carry = 0;
+
+            for place in 0..self.0.len().max(other.0.len()) {
+                let digit = *self.0.get(place).unwrap_or(&0) as u64
+                    + *other.0.get(place).unwrap_or(&0) as u64
+                    + carry;
+                sum.push(digit as u32);
+                carry = digit >> 32;
+            }
+
+            sum.push(carry as u32);
+            Some(Big::trim(sum))
+        }
+
+        fn checked_sub(&self, other: &Self) -> Option<Self> {
+            if self < other {
+                return None;
+            }
+
+            let mut difference = Vec::new();
+            let mut borrow = 0;
+
+            for (place, &digit) in self.0.iter().enumerate() {
+                let (digit, under) = digit.overflowing_sub(*other.0.get(place).unwrap_or(&0));
+                let (digit, under_again) = digit.overflowing_sub(borrow);
+                difference.push(digit);
+                borrow = (under || under_again) as u32;
+            }
+
+            Some(Big::trim(difference))
+        }
+
+        fn checked_mul(&self, other: &Self) -> Option<Self> {
+            let mut product = vec![0; self.0.len() + other.0.len()];
+
+            for (i, &left) in self.0.iter().enumerate() {
+                let mut carry = 0;
+
+                for (j, &right) in other.0.iter().enumerate() {
+                    let digit = left as u64 * right as u64 + product[i + j] as u64 + carry;
+                    product[i + j] = digit as u32;
+                    carry = digit >> 32;
+                }
+
+                product[i + other.0.len()] = carry as u32;
+            }
+
+            Some(Big::trim(product))
+        }
+
+        fn checked_div(&self, other: &Self) -> Option<Self> {
+            match other.0[..] {
+                [] => None,
+                [divisor] => Some(self.div_rem_small(divisor).0),
+                _ => {
+                    // Schoolbook long division, one bit at a time. Slow, but only for huge
+                    // divisors.
+                    let mut quotient = vec![0; self.0.len()];
+                    let mut remainder = Big(Vec::new());
+
+                    for bit in (0..self.bits()).rev() {
+                        remainder = remainder.checked_add(&remainder)?;
+                        if self.bit(bit) {
+                            remainder = remainder.checked_add(&Big(vec![1]))?;
+                        }
+
+                        if remainder >= *other {
+                            remainder = remainder.checked_sub(other)?;
+                            quotient[bit / 32] |= 1 << (bit % 32);
+                        }
+                    }
+
+                    Some(Big::trim(quotient))
+                }
+            }
+        }
+
+        fn rem(&self, modulus: Worry) -> Worry {
+            let modulus = modulus as u128;
+            self.0.iter()
+                .rev()
+                .fold(0, |remainder, &digit| (remainder << 32 | digit as u128) % modulus) as Worry
+        }
+    }
+
+    impl fmt::Display for Big {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            // Peel off nine decimal digits at a time.
+            let mut chunks = Vec::new();
+            let mut rest = self.clone();
+
+            while !rest.0.is_empty() {
+                let (quotient, chunk) = rest.div_rem_small(1_000_000_000);
+                chunks.push(chunk);
+                rest = quotient;
+            }
+
+            match chunks.split_last() {
+                None => write!(f, "0"),
+                Some((top, rest)) => {
+                    write!(f, "{}", top)?;
+                    for chunk in rest.iter().rev() {
+                        write!(f, "{:09}", chunk)?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+    }
 }
 
 #[test]
@@ -187,12 +1147,12 @@ fn read_zeroth_monkey() {
     assert_eq!(zero.label, 0);
     assert_eq!(zero.items, VecDeque::from([79, 98]));
 
-    assert_eq!((zero.inspect)(1), 19);
-    assert_eq!((zero.inspect)(2), 38);
+    assert_eq!(zero.inspect.eval(&1).unwrap(), 19);
+    assert_eq!(zero.inspect.eval(&2).unwrap(), 38);
 
-    assert!((zero.throw.test)(23));
-    assert!((zero.throw.test)(46));
-    assert!(!(zero.throw.test)(23 - 1));
+    assert!(zero.throw.test(&(23)));
+    assert!(zero.throw.test(&(46)));
+    assert!(!zero.throw.test(&(23 - 1)));
 
     assert_eq!(zero.throw.success, 2);
     assert_eq!(zero.throw.failure, 3);
@@ -212,14 +1172,473 @@ fn read_second_monkey() {
     assert_eq!(two.label, 2);
     assert_eq!(two.items, VecDeque::from([79, 60, 97]));
 
-    assert_eq!((two.inspect)(1), 1);
-    assert_eq!((two.inspect)(2), 4);
-    assert_eq!((two.inspect)(3), 9);
+    assert_eq!(two.inspect.eval(&1).unwrap(), 1);
+    assert_eq!(two.inspect.eval(&2).unwrap(), 4);
+    assert_eq!(two.inspect.eval(&3).unwrap(), 9);
 
-    assert!((two.throw.test)(13));
-    assert!((two.throw.test)(26));
-    assert!(!(two.throw.test)(13 - 1));
+    assert!(two.throw.test(&(13)));
+    assert!(two.throw.test(&(26)));
+    assert!(!two.throw.test(&(13 - 1)));
 
     assert_eq!(two.throw.success, 1);
     assert_eq!(two.throw.failure, 3);
 }
+
+#[test]
+fn print_monkeys_back() {
+    let puzzle = fs::read_to_string("sample.txt").unwrap();
+    let monkeys = read_monkeys(&puzzle).unwrap();
+
+    let printed = monkeys.iter()
+        .map(|monkey| monkey.to_string())
+        .collect::<Vec<String>>()
+        .join("\n\n");
+
+    assert_eq!(printed, puzzle.trim_end());
+    assert_eq!(read_monkeys(&printed).unwrap(), monkeys);
+    assert_eq!(monkeys[2].inspect.to_string(), "old * old");
+}
+
+#[test]
+fn snapshot_simulation() {
+    let puzzle = fs::read_to_string("sample.txt").unwrap();
+    let mut monkeys = read_monkeys(&puzzle).unwrap();
+
+    round(&mut monkeys, false, None).unwrap();
+    let snapshot = monkeys.clone();
+
+    round(&mut monkeys, false, None).unwrap();
+    assert_ne!(monkeys, snapshot);
+
+    // A snapshot printed to notes picks up where it left off, apart from the inspection counts.
+    let mut resumed = read_monkeys(
+        &snapshot.iter().map(|monkey| monkey.to_string()).collect::<Vec<String>>().join("\n\n")
+    ).unwrap();
+    round(&mut resumed, false, None).unwrap();
+
+    let items = |monkeys: &[Monkey]| monkeys.iter()
+        .map(|monkey| monkey.items.clone())
+        .collect::<Vec<VecDeque<Worry>>>();
+
+    assert_eq!(items(&resumed), items(&monkeys));
+    let monkeys = read_monkeys(&puzzle).unwrap();
+    assert_eq!(
+        simulate(&snapshot, 19, false, false).unwrap().monkey_business(),
+        simulate(&monkeys, 20, false, false).unwrap().monkey_business()
+    );
+}
+
+#[test]
+fn read_operations() -> Result<(), String> {
+    let old = || Box::new(Expr::Old);
+    let int = |int| Box::new(Expr::Int(int));
+
+    assert_eq!("old * 19".parse::<Expr>()?, Expr::Mul(old(), int(19)));
+    assert_eq!("3 - old".parse::<Expr>()?, Expr::Sub(int(3), old()));
+
+    assert_eq!(
+        "old + 2 * old".parse::<Expr>()?,
+        Expr::Add(old(), Box::new(Expr::Mul(int(2), old())))
+    );
+
+    assert_eq!(
+        "(old + 2) * old".parse::<Expr>()?,
+        Expr::Mul(Box::new(Expr::Add(old(), int(2))), old())
+    );
+
+    assert_eq!(
+        "old - 1 - 1".parse::<Expr>()?,
+        Expr::Sub(Box::new(Expr::Sub(old(), int(1))), int(1))
+    );
+
+    let expr = "(old * old + 7) / (2 * 3) - old / 4".parse::<Expr>()?;
+    assert_eq!(expr.eval(&10), Ok((10 * 10 + 7) / 6 - 10 / 4));
+
+    for oops in ["", "old +", "old ** 2", "(old", "old)", "new", "old % 2", "2 old"] {
+        assert!(oops.parse::<Expr>().is_err(), "{:?} should not parse", oops);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn print_operations_back() -> Result<(), String> {
+    for operation in [
+        "old * 19",
+        "old + 2 * old",
+        "(old + 2) * old",
+        "old - (1 - old)",
+        "old / 2 / 3",
+        "old / (2 / 3)",
+        "(old - 1) * (old + 1) - 4",
+    ] {
+        let expr = operation.parse::<Expr>()?;
+        assert_eq!(expr.to_string(), operation);
+        assert_eq!(expr.to_string().parse::<Expr>()?, expr);
+    }
+
+    assert_eq!("((old)) * (19)".parse::<Expr>()?.to_string(), "old * 19");
+
+    Ok(())
+}
+
+#[test]
+fn reject_malformed_notes() {
+    let puzzle = fs::read_to_string("sample.txt").unwrap();
+
+    let broken = |from: &str, to: &str| read_monkeys(&puzzle.replacen(from, to, 1)).unwrap_err();
+
+    assert_eq!(
+        broken("Starting items: 79, 98", "Starting items: 79, 9B"),
+        "line 2: expect worry level, found `9B`"
+    );
+    assert_eq!(
+        broken("Starting items: 79, 98", "Starting items: 79,, 98"),
+        "line 2: expect worry level, found ``"
+    );
+    assert_eq!(
+        broken("Monkey 1:", "Monkey 1"),
+        "line 8: expect `Monkey ...:`, found `Monkey 1`"
+    );
+    assert_eq!(
+        broken("Monkey 1:", "Monkey 4:"),
+        "expect monkey 1, found monkey 4"
+    );
+    assert_eq!(
+        broken("divisible by 23", "divisible by 0"),
+        "line 4: expect divisor to be positive"
+    );
+    assert_eq!(
+        broken("If true: throw to monkey 2", "If true: throw to monkey 7"),
+        "monkey 0 throws to monkey 7, which doesn't exist"
+    );
+    assert_eq!(
+        broken("If true: throw to monkey 2", "If true: throw to monkey 0"),
+        "monkey 0 throws to itself"
+    );
+    assert_eq!(
+        broken("    If false: throw to monkey 3\n", ""),
+        "line 5: expect `If false: throw to monkey ` after this line"
+    );
+    assert_eq!(
+        broken("new = old * 19", "new = old ^ 19"),
+        "line 3: unexpected '^' in operation"
+    );
+    assert_eq!(
+        broken("    If false: throw to monkey 3\n", "    If false: throw to monkey 3\n  Oops\n"),
+        "line 7: unexpected `Oops` after monkey 0"
+    );
+
+    assert!(read_monkeys("\n\n").is_err());
+}
+
+#[test]
+fn read_monkey_without_items() {
+    let empty = r"Monkey 0:
+        Starting items:
+        Operation: new = old
+        Test: divisible by 2
+          If true: throw to monkey 1
+          If false: throw to monkey 1";
+
+    assert_eq!(read_monkey(empty).unwrap().items, VecDeque::new());
+}
+
+#[test]
+fn report_overflow() {
+    let expr = "old * old - 2".parse::<Expr>().unwrap();
+
+    assert_eq!(expr.eval(&3), Ok(7));
+    assert_eq!(expr.eval(&1), Err("`old * old - 2` goes below zero when old = 1".to_string()));
+    assert_eq!(
+        expr.eval(&(1 << 40)),
+        Err(format!("`old * old` overflows when old = {}", 1_usize << 40))
+    );
+
+    let expr = "old / (old - 5)".parse::<Expr>().unwrap();
+    assert_eq!(expr.eval(&5), Err("`old / (old - 5)` divides by zero when old = 5".to_string()));
+
+    // Exact worry levels never overflow.
+    let big = "old * old * old".parse::<Expr>().unwrap().eval(&Big::from_worry(1 << 40)).unwrap();
+    assert_eq!(big.to_string(), "1329227995784915872903807060280344576");
+    assert_eq!(big.rem(1_000_000_007), 489373567);
+}
+
+#[test]
+fn big_arithmetic() {
+    let big = |worry| Big::from_worry(worry);
+
+    let huge = big(usize::MAX).checked_mul(&big(usize::MAX)).unwrap();
+    assert_eq!(huge.to_string(), "340282366920938463426481119284349108225");
+
+    assert_eq!(huge.checked_div(&big(usize::MAX)), Some(big(usize::MAX)));
+    assert_eq!(huge.checked_sub(&huge), Some(big(0)));
+    assert_eq!(big(1).checked_sub(&big(2)), None);
+    assert_eq!(big(7).checked_div(&big(0)), None);
+    assert_eq!(big(0).to_string(), "0");
+
+    let sum = huge.checked_add(&big(1_000_000_000)).unwrap();
+    assert_eq!(sum.to_string(), "340282366920938463426481119285349108225");
+    assert_eq!(sum.rem(10_000), 8225);
+}
+
+#[test]
+fn verify_item_modulus() {
+    let puzzle = fs::read_to_string("sample.txt").unwrap();
+    let monkeys = read_monkeys(&puzzle).unwrap();
+
+    assert_eq!(verify(&monkeys, 6, true), Ok(None));
+}
+
+#[test]
+fn divide_without_item_modulus() {
+    // Halving after multiplying by 19 loses track of the item modulus by round 8, so the worry
+    // levels have to stay exact until they get too big.
+    let puzzle = fs::read_to_string("sample.txt").unwrap()
+        .replace("old * 19\n", "old * 19 / 2\n");
+    let monkeys = read_monkeys(&puzzle).unwrap();
+    assert_eq!(verify(&monkeys, 40, true), Ok(Some(8)));
+
+    let rounds = 12;
+    let mut exact = monkeys.iter().map(Monkey::exact).collect::<Vec<Monkey<Big>>>();
+
+    for _ in 0..rounds {
+        round(&mut exact, true, None).unwrap();
+    }
+
+    let simulation = simulate(&monkeys, rounds, true, false).unwrap();
+
+    for (monkey, exact) in simulation.monkeys.iter().zip(&exact) {
+        assert_eq!(monkey.inspections, exact.inspections);
+    }
+
+    let reason = "monkey 0 does `old * 19 / 2`, which doesn't work modulo the divisors";
+    let overflow = simulate(&monkeys, 10000, true, false).unwrap_err();
+    assert!(overflow.ends_with(&format!("({})", reason)), "{}", overflow);
+    assert_eq!(extrapolate(&monkeys, 10000), Err(reason.to_string()));
+    assert_eq!(simulate_parallel(&monkeys, 10000, 2).unwrap_err(), reason);
+}
+
+#[test]
+fn verify_overflowing_item_modulus() {
+    // The item modulus is right for adding and multiplying, but too big to square in a `Worry`.
+    let notes = "\
+Monkey 0:
+  Starting items: 3
+  Operation: new = old * old
+  Test: divisible by 4294967291
+    If true: throw to monkey 1
+    If false: throw to monkey 1
+
+Monkey 1:
+  Starting items:
+  Operation: new = old + 1
+  Test: divisible by 4294967279
+    If true: throw to monkey 0
+    If false: throw to monkey 0
+";
+    let monkeys = read_monkeys(notes).unwrap();
+
+    let message = verify(&monkeys, 6, true).unwrap_err();
+    assert!(message.starts_with("modular run fails at round 6: monkey 0: `old * old` overflows"));
+}
+
+#[test]
+fn subtract_without_item_modulus() {
+    let notes = "\
+Monkey 0:
+  Starting items: 2
+  Operation: new = old + 10
+  Test: divisible by 2
+    If true: throw to monkey 1
+    If false: throw to monkey 1
+
+Monkey 1:
+  Starting items:
+  Operation: new = old - 1
+  Test: divisible by 3
+    If true: throw to monkey 0
+    If false: throw to monkey 0
+";
+    let monkeys = read_monkeys(notes).unwrap();
+
+    // 12 reduces to 0 before monkey 1 gets to subtract one.
+    assert_eq!(verify(&monkeys, 3, true), Ok(Some(1)));
+    assert_eq!(simulate(&monkeys, 10000, true, false).unwrap().monkey_business(), (10000, 10000));
+}
+
+#[test]
+fn follow_sample_item() {
+    let puzzle = fs::read_to_string("sample.txt").unwrap();
+    let monkeys = read_monkeys(&puzzle).unwrap();
+    let item_modulus = item_modulus(&monkeys).unwrap();
+
+    // Monkey 0 inspects 79 and throws 1501 to monkey 3, which throws 1504 to monkey 1. That's an
+    // earlier monkey, so the round is over. Next round monkey 1 throws 1510 back to monkey 0.
+    let trajectory = follow(&monkeys, 0, 79, item_modulus).unwrap();
+    assert_eq!(trajectory.round(0), &[0, 3]);
+    assert_eq!(trajectory.round(1), &[1]);
+
+    let rounds = (trajectory.start + 2 * trajectory.period + 3) as u64;
+    let inspections = trajectory.inspections(rounds, monkeys.len());
+
+    let expected = (0..rounds as usize)
+        .map(|round| trajectory.round(if round < trajectory.start {
+            round
+        } else {
+            trajectory.start + (round - trajectory.start) % trajectory.period
+        }))
+        .fold(vec![0; monkeys.len()], |mut inspections, visits| {
+            visits.iter().for_each(|&monkey| inspections[monkey] += 1);
+            inspections
+        });
+
+    assert_eq!(inspections, expected);
+}
+
+#[test]
+fn extrapolate_sample() {
+    let puzzle = fs::read_to_string("sample.txt").unwrap();
+    let monkeys = read_monkeys(&puzzle).unwrap();
+
+    for rounds in [1, 20, 1000, 10000] {
+        let (largest, second_largest) = simulate(&monkeys, rounds, true, false).unwrap()
+            .monkey_business();
+        assert_eq!(
+            extrapolate(&monkeys, rounds as u64),
+            Ok((largest as u64, second_largest as u64))
+        );
+    }
+
+    assert!(extrapolate(&monkeys, 1_000_000_000_000).is_ok());
+}
+
+#[test]
+fn report_sample_rounds() {
+    let puzzle = fs::read_to_string("sample.txt").unwrap();
+    let monkeys = read_monkeys(&puzzle).unwrap();
+
+    let simulation = simulate(&monkeys, 20, false, true).unwrap();
+
+    assert_eq!(simulation.monkey_business(), (105, 101));
+    assert_eq!(simulation.busiest(10), vec![(3, 105), (0, 101), (1, 95), (2, 7)]);
+    assert_eq!(simulation.snapshots.len(), 20);
+
+    // After round one the puzzle says monkeys 0 and 1 hold four and six items, and monkeys 2 and
+    // 3 threw everything they caught.
+    let first = &simulation.snapshots[0];
+    assert_eq!(first.items, vec![4, 6, 0, 0]);
+    assert_eq!(first.inspections, vec![2, 4, 3, 5]);
+    assert_eq!(first.throws[0], vec![0, 0, 0, 2]);
+
+    let last = simulation.snapshots.last().unwrap();
+    assert_eq!(last.inspections, vec![101, 95, 7, 105]);
+
+    // Every inspection ends in a throw.
+    for snapshot in &simulation.snapshots {
+        for (monkey, throws) in snapshot.throws.iter().enumerate() {
+            assert_eq!(throws.iter().sum::<usize>(), snapshot.inspections[monkey]);
+        }
+    }
+
+    let csv = simulation.csv();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("round,monkey,items,inspections,throws_to_0,throws_to_1,throws_to_2,throws_to_3")
+    );
+    assert_eq!(lines.next(), Some("1,0,4,2,0,0,0,2"));
+    assert_eq!(csv.lines().count(), 1 + 20 * 4);
+}
+
+#[test]
+fn draw_sample_throws() {
+    let puzzle = fs::read_to_string("sample.txt").unwrap();
+    let monkeys = read_monkeys(&puzzle).unwrap();
+
+    let simulation = simulate(&monkeys, 1, false, false).unwrap();
+    let dot = simulation.dot();
+
+    assert!(dot.starts_with("digraph monkeys {\n"));
+    assert!(dot.contains("    2 [label=\"Monkey 2\\nnew = old * old\\ndivisible by 13\"];\n"));
+
+    // In the first round monkey 0 throws both its items to monkey 3, and monkey 3 throws everything
+    // to monkey 1.
+    assert!(dot.contains("    0 -> 2 [label=\"true: 0\", weight=0, penwidth=1.00];\n"));
+    assert!(dot.contains("    0 -> 3 [label=\"false: 2\", weight=2, penwidth=2.60];\n"));
+    assert!(dot.contains("    3 -> 1 [label=\"false: 5\", weight=5, penwidth=5.00];\n"));
+    assert!(dot.ends_with("}\n"));
+
+    let puzzle = puzzle.replace("If true: throw to monkey 2", "If true: throw to monkey 3");
+    let simulation = simulate(&read_monkeys(&puzzle).unwrap(), 1, false, false).unwrap();
+    let dot = simulation.dot();
+
+    assert!(dot.contains("    0 -> 3 [label=\"either: 2\", weight=2, penwidth=2.60];\n"));
+    assert!(!dot.contains("    0 -> 3 [label=\"true"));
+}
+
+#[test]
+fn parallel_matches_rounds() {
+    let sorted = |monkeys: &[Monkey]| {
+        monkeys.iter()
+            .map(|monkey| {
+                let mut items = monkey.items.iter().copied().collect::<Vec<Worry>>();
+                items.sort_unstable();
+                (monkey.inspections, monkey.throws.clone(), items)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for file in ["sample.txt", "input.txt"] {
+        let puzzle = fs::read_to_string(file).unwrap();
+        let monkeys = read_monkeys(&puzzle).unwrap();
+
+        for rounds in [0, 1, 20, 1000] {
+            let expected = simulate(&monkeys, rounds, true, false).unwrap();
+
+            for threads in [1, 2, 3, 64] {
+                let actual = simulate_parallel(&monkeys, rounds, threads).unwrap();
+                assert_eq!(sorted(&actual.monkeys), sorted(&expected.monkeys));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn crowd(monkeys: &[Monkey], items: usize) -> Vec<Monkey> {
+    // The same monkeys, only each one starts out holding a lot more items. Worry levels count up
+    // from the monkey's own items so they don't all go the same way.
+    monkeys.iter()
+        .map(|monkey| {
+            let first = monkey.items.iter().copied().min().unwrap_or(1);
+
+            Monkey {
+                items: (first..first + items).collect(),
+                ..monkey.clone()
+            }
+        })
+        .collect()
+}
+
+#[test]
+#[ignore]
+fn bench_parallel_simulation() {
+    // Too slow for a debug build. `cargo test --release bench -- --ignored --nocapture` prints
+    // how long each takes.
+
+    use std::time::Instant;
+
+    let puzzle = fs::read_to_string("input.txt").unwrap();
+    let monkeys = crowd(&read_monkeys(&puzzle).unwrap(), 200);
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+
+    let now = Instant::now();
+    let expected = simulate(&monkeys, 10000, true, false).unwrap();
+    println!("one thread: {:?}", now.elapsed());
+
+    let now = Instant::now();
+    let actual = simulate_parallel(&monkeys, 10000, threads).unwrap();
+    println!("{} threads: {:?}", threads, now.elapsed());
+
+    assert_eq!(actual.monkey_business(), expected.monkey_business());
+}
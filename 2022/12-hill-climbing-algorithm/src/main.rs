@@ -1,4 +1,5 @@
-use std::collections::{HashSet, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
 use std::env;
 use std::error::Error;
 use std::fs;
@@ -9,75 +10,277 @@ type Position = usize;
 struct Grid {
     width: usize,
     height: usize,
-    start: Position,
-    goal: Position,
+    // Trailheads and summits. The puzzle only has one of each.
+    starts: Vec<Position>,
+    goals: Vec<Position>,
     squares: Vec<u8>,
 }
 
+#[derive(Clone, Copy, Debug)]
 enum Step {
-    North, South, East, West
+    North, South, East, West,
+    NorthEast, SouthEast, SouthWest, NorthWest,
+}
+
+const STEPS: [Step; 4] = [Step::North, Step::East, Step::South, Step::West];
+
+const DIAGONALS: [Step; 4] = [Step::NorthEast, Step::SouthEast, Step::SouthWest, Step::NorthWest];
+
+impl Step {
+    fn arrow(self) -> char {
+        match self {
+            Step::North => '^',
+            Step::South => 'v',
+            Step::East  => '>',
+            Step::West  => '<',
+            // Close enough, there aren't any diagonal arrows in ASCII.
+            Step::NorthEast | Step::SouthWest => '/',
+            Step::SouthEast | Step::NorthWest => '\\',
+        }
+    }
+}
+
+// What a hiker is allowed to do and what it costs them. The default is the puzzle: climb at most
+// one, descend as far as you like, and every step costs the same.
+#[derive(Clone, Debug)]
+struct Rules {
+    max_climb: u8,
+    max_descent: Option<u8>,
+    diagonals: bool,
+    step_cost: usize,
+    // Extra cost for every unit of elevation climbed.
+    climb_cost: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Strategy {
+    Dijkstra, AStar
+}
+
+// Forward searches climb the way a hiker would. Reverse searches walk down from where the hiker
+// wants to end up, only taking steps the hiker could take back up.
+#[derive(Clone, Copy, Debug)]
+enum Search {
+    Forward, Reverse
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = env::args().collect::<Vec<String>>();
 
     if args.len() < 2 {
-        return Err(Box::from("usage: cargo run -- puzzle-input"));
+        return Err(Box::from(
+            "usage: cargo run -- puzzle-input [--draw] [--dijkstra] [--max-climb n] \
+             [--max-descent n] [--diagonals] [--step-cost n] [--climb-cost n]"
+        ));
+    }
+
+    let mut draw = false;
+    let mut strategy = Strategy::AStar;
+    let mut rules: Option<Rules> = None;
+
+    let mut options = args[2..].iter();
+
+    while let Some(flag) = options.next() {
+        let mut value = || options.next().ok_or(format!("expect value after `{}`", flag));
+
+        match flag.as_str() {
+            "--draw" => draw = true,
+            "--dijkstra" => {
+                // Asking for a planner means planning, with the puzzle's rules if nothing else.
+                strategy = Strategy::Dijkstra;
+                rules.get_or_insert_with(Rules::default);
+            }
+            rule => {
+                // Any of the movement rules means planning a weighted hike as well.
+                let rules = rules.get_or_insert_with(Rules::default);
+
+                match rule {
+                    "--max-climb" => rules.max_climb = value()?.parse()?,
+                    "--max-descent" => rules.max_descent = Some(value()?.parse()?),
+                    "--diagonals" => rules.diagonals = true,
+                    "--step-cost" => rules.step_cost = value()?.parse()?,
+                    "--climb-cost" => rules.climb_cost = value()?.parse()?,
+                    oops => return Err(Box::from(format!("unexpected option `{}`", oops))),
+                }
+            }
+        }
     }
 
     let puzzle = fs::read_to_string(&args[1])?;
 
     let grid = puzzle.parse::<Grid>()?;
 
-    let to_summit = hike(&grid, grid.start);
+    let path = hike(&grid, &grid.starts);
+
+    println!("part one: {:?}", path.as_ref().map(|path| path.len() - 1));
+
+    if let Some(path) = path.filter(|_| draw) {
+        println!("{}", grid.draw(&path));
+    }
+
+    // One search down from the summit tells us how far every square is from the top.
+    let from_summit = grid.distances(&grid.goals, Search::Reverse);
+    let nearest = grid.nearest(&from_summit, 0);
 
-    println!("part one: {:?}", to_summit);
+    println!("part two: {:?}", nearest.map(|(_, distance)| distance));
 
-    // Can't refactor to reverse the breadth-first search I'm sorry core.
+    if let Some((start, _)) = nearest.filter(|_| draw) {
+        // There's a path, we just measured it.
+        println!("{}", grid.draw(&hike(&grid, &[start]).unwrap()));
+    }
 
-    let to_summit = grid.elevation(0).iter()
-        .flat_map(|&start| hike(&grid, start))
-        .min();
+    if let Some(rules) = rules {
+        match plan(&grid, &grid.starts, &rules, strategy) {
+            Some((cost, path)) => {
+                println!("planned: cost {} over {} steps", cost, path.len() - 1);
 
-    println!("part two: {:?}", to_summit);
+                if draw {
+                    println!("{}", grid.draw(&path));
+                }
+            }
+            None => println!("planned: no route"),
+        }
+    }
 
     Ok(())
 }
 
-fn hike(grid: &Grid, start: Position) -> Option<usize> {
-    let mut discovered: VecDeque<(usize, Position)> = VecDeque::from([(0, start)]);
-    let mut seen: HashSet<Position> = HashSet::from([start]);
+fn hike(grid: &Grid, starts: &[Position]) -> Option<Vec<Position>> {
+    // The squares on a shortest route from any of `starts` to the nearest summit, both ends
+    // included.
+    let distances = grid.distances(starts, Search::Forward);
+
+    let mut position = grid.goals.iter()
+        .copied()
+        .filter(|&goal| distances[goal].is_some())
+        .min_by_key(|&goal| distances[goal])?;
+
+    let mut distance = distances[position]?;
+    let mut path = vec![position];
+
+    // Walk back from the summit, each time to a square one step closer to a start that could
+    // have climbed to where we are.
+    while distance > 0 {
+        position = STEPS.into_iter()
+            .filter_map(|step| grid.neighbour(position, step))
+            .find(|&from| distances[from] == Some(distance - 1) && grid.lower(from, position))
+            .unwrap();
+
+        distance -= 1;
+        path.push(position);
+    }
+
+    path.reverse();
 
-    while let Some((distance, position)) = discovered.pop_front() {
-        if position == grid.goal {
-            return Some(distance);
+    Some(path)
+}
+
+fn plan(
+    grid: &Grid, starts: &[Position], rules: &Rules, strategy: Strategy
+) -> Option<(usize, Vec<Position>)> {
+    // The cheapest route from any of `starts` to any summit under `rules`, and what it costs. A*
+    // only looks at squares that might still be on a cheaper route, but always agrees with
+    // Dijkstra.
+    let estimate = |position: Position| match strategy {
+        Strategy::Dijkstra => 0,
+        Strategy::AStar => grid.goals.iter()
+            .map(|&goal| rules.estimate(grid, position, goal))
+            .min()
+            .unwrap_or(0),
+    };
+
+    let mut costs = vec![None; grid.squares.len()];
+    let mut previous = vec![None; grid.squares.len()];
+    let mut frontier = BinaryHeap::new();
+
+    for &start in starts {
+        costs[start] = Some(0);
+        frontier.push(Reverse((estimate(start), 0, start)));
+    }
+
+    while let Some(Reverse((_, cost, position))) = frontier.pop() {
+        if grid.goals.contains(&position) {
+            let mut path = vec![position];
+
+            while let Some(from) = previous[*path.last().unwrap()] {
+                path.push(from);
+            }
+
+            path.reverse();
+
+            return Some((cost, path));
         }
 
-        let adjacent =
-            [ grid.step(position, Step::North),
-              grid.step(position, Step::East),
-              grid.step(position, Step::South),
-              grid.step(position, Step::West),
-            ].into_iter()
-            .filter_map(|position| position)
-            .filter(|position| !seen.contains(position))
-            .collect::<Vec<Position>>();
+        if costs[position].is_some_and(|best| best < cost) {
+            // We found a cheaper way here after this one was queued.
+            continue;
+        }
+
+        let steps = if rules.diagonals { &[STEPS, DIAGONALS][..] } else { &[STEPS][..] };
+
+        for &step in steps.iter().flatten() {
+            let next = match grid.neighbour(position, step) {
+                Some(next) => next,
+                None => continue,
+            };
+
+            let (from, to) = (grid.squares[position], grid.squares[next]);
+
+            if !rules.allows(from, to) {
+                continue;
+            }
 
-        seen.extend(&adjacent);
+            let cost = cost + rules.cost(from, to);
 
-        discovered.extend(
-            adjacent.into_iter()
-            .map(|position| (distance + 1, position))
-        );
+            if costs[next].is_none_or(|best| cost < best) {
+                costs[next] = Some(cost);
+                previous[next] = Some(position);
+                frontier.push(Reverse((cost + estimate(next), cost, next)));
+            }
+        }
     }
 
     None
 }
 
+impl Default for Rules {
+    fn default() -> Self {
+        Rules { max_climb: 1, max_descent: None, diagonals: false, step_cost: 1, climb_cost: 0 }
+    }
+}
+
+impl Rules {
+    fn allows(&self, from: u8, to: u8) -> bool {
+        let climb = to.saturating_sub(from);
+        let descent = from.saturating_sub(to);
+        climb <= self.max_climb && self.max_descent.is_none_or(|most| descent <= most)
+    }
+
+    fn cost(&self, from: u8, to: u8) -> usize {
+        self.step_cost + self.climb_cost * to.saturating_sub(from) as usize
+    }
+
+    fn estimate(&self, grid: &Grid, from: Position, to: Position) -> usize {
+        // Never more than the real cost, so A* still finds the cheapest route. We need at least
+        // as many steps as the squares are apart, and enough of them to make the climb, and we
+        // pay for every unit of the climb at least once.
+        let ((row, column), (goal_row, goal_column)) =
+            (grid.coordinates(from).unwrap(), grid.coordinates(to).unwrap());
+
+        let (rows, columns) = (row.abs_diff(goal_row), column.abs_diff(goal_column));
+        let apart = if self.diagonals { rows.max(columns) } else { rows + columns };
+
+        let climb = grid.squares[to].saturating_sub(grid.squares[from]) as usize;
+        let steps = apart.max(climb.div_ceil(self.max_climb.max(1) as usize));
+
+        steps * self.step_cost + climb * self.climb_cost
+    }
+}
+
 impl Grid {
     fn offset(&self, (row, column): (usize, usize)) -> Option<Position> {
-        let offset = self.width * row + column;
-        self.valid_offset(offset).then_some(offset)
+        // Check the column too, otherwise stepping east off the end of a row wraps to the next.
+        (row < self.height && column < self.width).then_some(self.width * row + column)
     }
 
     fn valid_offset(&self, offset: usize) -> bool {
@@ -89,28 +292,100 @@ impl Grid {
         Some((offset / self.width, offset % self.width))
     }
 
-    fn step(&self, offset: usize, step: Step) -> Option<Position> {
+    fn neighbour(&self, offset: usize, step: Step) -> Option<Position> {
         let (row, column) = self.coordinates(offset)?;
 
-        // TODO: Yikes. I have all the stack space in the world please don't yell at me.
-        let north = (row > 0).then(|| self.offset((row - 1, column)).unwrap());
-        let east  = self.offset((row, column + 1));
-        let south = self.offset((row + 1, column));
-        let west  = (column > 0).then(|| self.offset((row, column - 1)).unwrap());
-
         match step {
-            Step::North if self.lower(offset, north?) => north,
-            Step::East  if self.lower(offset, east?)  => east,
-            Step::South if self.lower(offset, south?) => south,
-            Step::West  if self.lower(offset, west?)  => west,
-            _ => None,
+            Step::North => self.offset((row.checked_sub(1)?, column)),
+            Step::East  => self.offset((row, column + 1)),
+            Step::South => self.offset((row + 1, column)),
+            Step::West  => self.offset((row, column.checked_sub(1)?)),
+            Step::NorthEast => self.neighbour(self.neighbour(offset, Step::North)?, Step::East),
+            Step::SouthEast => self.neighbour(self.neighbour(offset, Step::South)?, Step::East),
+            Step::SouthWest => self.neighbour(self.neighbour(offset, Step::South)?, Step::West),
+            Step::NorthWest => self.neighbour(self.neighbour(offset, Step::North)?, Step::West),
         }
     }
 
+    fn step(&self, offset: usize, step: Step) -> Option<Position> {
+        self.neighbour(offset, step).filter(|&to| self.lower(offset, to))
+    }
+
     fn lower(&self, from: usize, to: usize) -> bool {
         self.squares[from] + 1 >= self.squares[to]
     }
 
+    fn distances(&self, sources: &[Position], search: Search) -> Vec<Option<usize>> {
+        // Breadth-first search from all the sources at once. Each square gets the distance from
+        // the nearest source, or to the nearest source when searching in reverse.
+        let mut distances = vec![None; self.squares.len()];
+        let mut discovered = VecDeque::new();
+
+        for &source in sources {
+            if distances[source].is_none() {
+                distances[source] = Some(0);
+                discovered.push_back((0, source));
+            }
+        }
+
+        while let Some((distance, position)) = discovered.pop_front() {
+            for step in STEPS {
+                let next = match search {
+                    Search::Forward => self.step(position, step),
+                    Search::Reverse => self.neighbour(position, step)
+                        .filter(|&next| self.lower(next, position)),
+                };
+
+                let next = match next {
+                    Some(next) => next,
+                    None => continue,
+                };
+
+                if distances[next].is_none() {
+                    distances[next] = Some(distance + 1);
+                    discovered.push_back((distance + 1, next));
+                }
+            }
+        }
+
+        distances
+    }
+
+    fn nearest(&self, distances: &[Option<usize>], elevation: u8) -> Option<(Position, usize)> {
+        // The closest square of some elevation, given the distances from a search.
+        self.elevation(elevation).into_iter()
+            .filter_map(|position| Some((position, distances[position]?)))
+            .min_by_key(|&(_, distance)| distance)
+    }
+
+    fn draw(&self, path: &[Position]) -> String {
+        // The heightmap with arrows along the path, like the puzzle draws it.
+        let mut picture = self.squares.iter()
+            .map(|&elevation| (b'a' + elevation) as char)
+            .collect::<Vec<char>>();
+
+        for &goal in &self.goals {
+            picture[goal] = 'E';
+        }
+
+        for pair in path.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+
+            let step = STEPS.into_iter()
+                .chain(DIAGONALS)
+                .find(|&step| self.neighbour(from, step) == Some(to));
+
+            if let Some(step) = step {
+                picture[from] = step.arrow();
+            }
+        }
+
+        picture.chunks(self.width)
+            .map(|row| row.iter().collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
     fn elevation(&self, desired: u8) -> Vec<Position> {
         self.squares.iter()
             .enumerate()
@@ -123,67 +398,59 @@ impl FromStr for Grid {
     type Err = String;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
-        let width = value.lines().next().ok_or("expect heightmap".to_string())?.len();
+        let width = value.lines().next().map_or(0, |line| line.chars().count());
+
+        if width == 0 {
+            return Err("expect heightmap".to_string());
+        }
+
         let mut squares = Vec::new();
+        let mut height = 0;
 
-        let mut height_offset = 0;
-        let mut offset: usize = 0;
+        let mut starts = Vec::new();
+        let mut goals = Vec::new();
 
-        let mut start = None;
-        let mut goal = None;
+        // Rows and columns count from one in error messages, like in an editor.
+        for (row, line) in (1..).zip(value.lines()) {
+            let length = line.chars().count();
 
-        for line in value.lines() {
-            for symbol in line.chars() {
-                match symbol {
+            if length != width {
+                return Err(format!(
+                    "expect row {} to be {} squares wide like row 1, found {}", row, width, length
+                ));
+            }
+
+            for (column, symbol) in (1..).zip(line.chars()) {
+                let elevation = match symbol {
                     'S' => {
-                        start = match start {
-                            None => Some(offset),
-                            Some(found) => return Err(
-                                format!("expect one 'S', found at offsets {} and {}", found, offset)
-                            ),
-                        };
-                        squares.push(b'a' - b'a')
+                        starts.push(squares.len());
+                        0
                     }
                     'E' => {
-                        goal = match goal {
-                            None => Some(offset),
-                            Some(found) => return Err(
-                                format!("expect one 'E', found at offsets {} and {}", found, offset)
-                            ),
-                        };
-                        squares.push(b'z' - b'a')
+                        goals.push(squares.len());
+                        b'z' - b'a'
                     }
-                     _  =>
-                        squares.push(
-                            if ('a'..='z').contains(&symbol) {
-                                symbol as u8 - b'a'
-                            } else {
-                                Err(format!("unexpected elevation '{}'", symbol))?
-                            }
-                        )
-                }
-                offset += 1;
+                    _ if symbol.is_ascii_lowercase() => symbol as u8 - b'a',
+                    _ => return Err(format!(
+                        "unexpected elevation '{}' at row {}, column {}", symbol, row, column
+                    )),
+                };
+
+                squares.push(elevation);
             }
-            height_offset += 1;
+
+            height += 1;
         }
 
-        let start = if start.is_none() {
-            Err("expect 'S'".to_string())?
-        } else {
-            start.unwrap()
-        };
+        if starts.is_empty() {
+            return Err("expect 'S'".to_string());
+        }
 
-        let goal = if goal.is_none() {
-            Err("expect 'E'".to_string())?
-        } else {
-            goal.unwrap()
-        };
+        if goals.is_empty() {
+            return Err("expect 'E'".to_string());
+        }
 
-        return Ok(Grid {
-            width, height: height_offset,
-            start, goal,
-            squares
-        })
+        Ok(Grid { width, height, starts, goals, squares })
     }
 }
 
@@ -202,8 +469,8 @@ abdefghi";
 
     assert_eq!(grid.width, 8);
     assert_eq!(grid.height, 5);
-    assert_eq!(grid.start, 0);
-    assert_eq!(grid.goal, 21);
+    assert_eq!(grid.starts, vec![0]);
+    assert_eq!(grid.goals, vec![21]);
 
     //       S  a  b   q   p   o   n   m
     if let &[0, 0, 1, 16, 15, 14, 13, 12, ..] = &grid.squares[..] {
@@ -223,7 +490,7 @@ abdefghi";
 
     let grid = cutie.parse::<Grid>()?;
 
-    let mut square = grid.start;
+    let mut square = grid.starts[0];
 
     assert_eq!(grid.step(square, Step::North), None);
 
@@ -253,3 +520,195 @@ abdefghi";
 
     Ok(())
 }
+
+#[test]
+fn search_down_from_summit() -> Result<(), String> {
+    let cutie = r"Sabqponm
+abcryxxl
+accszExk
+acctuvwj
+abdefghi";
+
+    let grid = cutie.parse::<Grid>()?;
+
+    let from_summit = grid.distances(&grid.goals, Search::Reverse);
+
+    for (position, &distance) in from_summit.iter().enumerate() {
+        assert_eq!(distance, hike(&grid, &[position]).map(|path| path.len() - 1));
+    }
+
+    assert_eq!(from_summit[grid.starts[0]], Some(31));
+    assert_eq!(grid.nearest(&from_summit, 0), Some((grid.offset((4, 0)).unwrap(), 29)));
+
+    // Starting from every `a` at once gets to the summit just as quickly.
+    let from_valleys = grid.distances(&grid.elevation(0), Search::Forward);
+    assert_eq!(from_valleys[grid.goals[0]], Some(29));
+
+    Ok(())
+}
+
+#[test]
+fn stay_on_the_row() -> Result<(), String> {
+    let grid = "Sb\naE".parse::<Grid>()?;
+
+    assert_eq!(grid.neighbour(1, Step::East), None);
+    assert_eq!(grid.neighbour(2, Step::West), None);
+
+    Ok(())
+}
+
+#[test]
+fn draw_small_grid_route() -> Result<(), String> {
+    let cutie = r"Sabqponm
+abcryxxl
+accszExk
+acctuvwj
+abdefghi";
+
+    let grid = cutie.parse::<Grid>()?;
+
+    let path = hike(&grid, &grid.starts).ok_or("expect path")?;
+
+    assert_eq!(path.len(), 32);
+    assert_eq!(path.first(), Some(&grid.starts[0]));
+    assert_eq!(path.last(), Some(&grid.goals[0]));
+
+    for pair in path.windows(2) {
+        assert!(STEPS.into_iter().any(|step| grid.step(pair[0], step) == Some(pair[1])));
+    }
+
+    let picture = grid.draw(&path);
+    assert_eq!(picture.lines().count(), grid.height);
+    assert_eq!(picture.chars().filter(|symbol| "^>v<".contains(*symbol)).count(), 31);
+    assert_eq!(picture.lines().nth(2).and_then(|row| row.chars().nth(5)), Some('E'));
+
+    Ok(())
+}
+
+#[test]
+fn draw_switchback() -> Result<(), String> {
+    let grid = "Sbcdefghijklm\nEyxwvutsrqpon".parse::<Grid>()?;
+
+    let path = hike(&grid, &grid.starts).ok_or("expect path")?;
+    assert_eq!(grid.draw(&path), ">>>>>>>>>>>>v\nE<<<<<<<<<<<<");
+
+    Ok(())
+}
+
+#[test]
+fn plan_with_puzzle_rules() -> Result<(), String> {
+    for file in ["sample.txt", "input.txt"] {
+        let grid = fs::read_to_string(file).unwrap().parse::<Grid>()?;
+        let steps = hike(&grid, &grid.starts).map(|path| path.len() - 1);
+
+        for strategy in [Strategy::Dijkstra, Strategy::AStar] {
+            let planned = plan(&grid, &grid.starts, &Rules::default(), strategy);
+            assert_eq!(planned.map(|(cost, path)| (cost, path.len() - 1)), steps.map(|n| (n, n)));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn plan_with_other_rules() -> Result<(), String> {
+    let grid = fs::read_to_string("input.txt").unwrap().parse::<Grid>()?;
+
+    let rules = [
+        Rules { climb_cost: 10, ..Rules::default() },
+        Rules { max_descent: Some(2), ..Rules::default() },
+        Rules { diagonals: true, ..Rules::default() },
+        Rules { max_climb: 3, diagonals: true, step_cost: 2, climb_cost: 5, ..Rules::default() },
+    ];
+
+    for rules in &rules {
+        let dijkstra = plan(&grid, &grid.starts, rules, Strategy::Dijkstra);
+        let a_star = plan(&grid, &grid.starts, rules, Strategy::AStar);
+
+        // The routes might differ, but not what they cost.
+        assert_eq!(dijkstra.as_ref().map(|(cost, _)| cost), a_star.as_ref().map(|(cost, _)| cost));
+
+        if let Some((cost, path)) = a_star {
+            let total = path.windows(2)
+                .map(|pair| {
+                    let (from, to) = (grid.squares[pair[0]], grid.squares[pair[1]]);
+                    assert!(rules.allows(from, to));
+                    rules.cost(from, to)
+                })
+                .sum::<usize>();
+
+            assert_eq!(total, cost);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn plan_around_cliffs() -> Result<(), String> {
+    // Over the `z` is shortest, but a careful hiker won't jump off it and goes around instead.
+    let grid = "SzaE\nbbbb".parse::<Grid>()?;
+
+    let cost = |rules: Rules| {
+        plan(&grid, &grid.starts, &rules, Strategy::AStar).map(|(cost, _)| cost)
+    };
+
+    let reckless = Rules { max_climb: 25, ..Rules::default() };
+    assert_eq!(cost(reckless.clone()), Some(3));
+
+    let careful = Rules { max_descent: Some(0), ..reckless };
+    assert_eq!(cost(careful.clone()), Some(5));
+    assert_eq!(cost(Rules { diagonals: true, ..careful }), Some(3));
+
+    // Climbing no higher than 255 is the same as climbing anywhere.
+    let grid = "SzbE".parse::<Grid>()?;
+    let fearless = Rules { max_climb: u8::MAX, ..Rules::default() };
+    let planned = plan(&grid, &grid.starts, &fearless, Strategy::AStar);
+    assert_eq!(planned.map(|(cost, _)| cost), Some(3));
+
+    Ok(())
+}
+
+#[test]
+fn reject_malformed_heightmaps() {
+    assert_eq!(
+        "Sab\nab\nabE".parse::<Grid>().err(),
+        Some("expect row 2 to be 3 squares wide like row 1, found 2".to_string())
+    );
+    assert_eq!(
+        "Sab\nab?\naEb".parse::<Grid>().err(),
+        Some("unexpected elevation '?' at row 2, column 3".to_string())
+    );
+    assert_eq!("".parse::<Grid>().err(), Some("expect heightmap".to_string()));
+    assert_eq!("aab\nabE".parse::<Grid>().err(), Some("expect 'S'".to_string()));
+    assert_eq!("Sab\naba".parse::<Grid>().err(), Some("expect 'E'".to_string()));
+}
+
+#[test]
+fn hike_between_trailheads_and_summits() -> Result<(), String> {
+    // The sample with another trailhead in the bottom left, and a summit off to the left that
+    // nobody can climb to.
+    let trails = r"Sabqponm
+abcryxxl
+EccszExk
+acctuvwj
+Sbdefghi";
+
+    let grid = trails.parse::<Grid>()?;
+
+    assert_eq!(grid.starts, vec![0, 32]);
+    assert_eq!(grid.goals, vec![16, 21]);
+
+    let path = hike(&grid, &grid.starts).ok_or("expect path")?;
+
+    assert_eq!(path.len() - 1, 29);
+    assert_eq!(path.first(), Some(&32));
+    assert_eq!(path.last(), Some(&21));
+
+    for strategy in [Strategy::Dijkstra, Strategy::AStar] {
+        let planned = plan(&grid, &grid.starts, &Rules::default(), strategy);
+        assert_eq!(planned.map(|(cost, _)| cost), Some(29));
+    }
+
+    Ok(())
+}
@@ -2,6 +2,7 @@ use std::cmp::Ord;
 use std::cmp::Ordering::{self, *};
 use std::env;
 use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::str::FromStr;
 
@@ -16,62 +17,136 @@ enum Entry {
 
 use self::Entry::*;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    Comma,
+    Int(u32),
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let args = env::args().collect::<Vec<String>>();
 
     if args.len() < 2 {
-        return Err(Box::from("usage: cargo run -- puzzle-input"));
+        return Err(Box::from("usage: cargo run -- puzzle-input [--sorted] [--json]"));
+    }
+
+    let mut sorted = false;
+    let mut json = false;
+
+    for flag in &args[2..] {
+        match flag.as_str() {
+            "--sorted" => sorted = true,
+            "--json" => json = true,
+            oops => return Err(Box::from(format!("unexpected option `{}`", oops))),
+        }
     }
 
-    let puzzle = fs::read_to_string(&args[1])?;
+    let mut puzzle = fs::read_to_string(&args[1])?;
 
-    let part_one = in_order_pairs(&puzzle)
-        .ok_or(Box::<dyn Error>::from("parse error".to_string()))?;
+    if json {
+        puzzle = read_json_puzzle(&puzzle)?;
+    }
+
+    let part_one = in_order_pairs(&puzzle)?;
 
     println!("part one: {}", part_one.into_iter().sum::<usize>());
 
-    println!("part two: {}", decoder_key(&puzzle));
+    println!("part two: {}", decoder_key(&puzzle)?);
+
+    for lines in duplicates(&puzzle)? {
+        let lines = lines.iter().map(|line| line.to_string()).collect::<Vec<String>>();
+        eprintln!("warning: packets on lines {} are equal", lines.join(", "));
+    }
+
+    if sorted {
+        for packet in sort_packets(&puzzle)? {
+            println!("{}", packet);
+        }
+    }
 
     Ok(())
 }
 
-fn in_order_pairs(puzzle: &str) -> Option<Vec<usize>> {
+fn in_order_pairs(puzzle: &str) -> Result<Vec<usize>, String> {
     let mut indices = Vec::new();
 
     for (index, pair) in (1..).zip(puzzle.split("\n\n")) {
         let mut pair = pair.lines();
 
-        let left = pair.next()?.parse::<Packet>().ok()?;
-        let right = pair.next()?.parse::<Packet>().ok()?;
+        let mut packet = |side| {
+            pair.next()
+                .ok_or(format!("pair {}: expect {} packet", index, side))?
+                .parse::<Packet>()
+                .map_err(|message| format!("pair {}, {} packet: {}", index, side, message))
+        };
+
+        let left = packet("left")?;
+        let right = packet("right")?;
 
         if left.in_order(&right) {
             indices.push(index);
         }
     }
 
-    Some(indices)
+    Ok(indices)
 }
 
-fn decoder_key(puzzle: &str) -> usize {
+fn dividers() -> (Packet, Packet) {
     let two = Packet(vec![List(Packet(vec![Int(2)]))]);
     let six = Packet(vec![List(Packet(vec![Int(6)]))]);
-    let mut packets = vec![two.clone(), six.clone()];
+    (two, six)
+}
 
-    // Interpret all lines as a packet and fail on empty ones.
-    for line in puzzle.lines() {
-        if let Ok(packet) = line.parse::<Packet>() {
-            packets.push(packet);
-        }
-    }
+fn read_packets(puzzle: &str) -> Result<Vec<(usize, Packet)>, String> {
+    // Every line that isn't empty is a packet. Lines count from one.
+    (1..).zip(puzzle.lines())
+        .filter(|(_, packet)| !packet.is_empty())
+        .map(|(line, packet)| {
+            let packet = packet.parse::<Packet>()
+                .map_err(|message| format!("line {}: {}", line, message))?;
+            Ok((line, packet))
+        })
+        .collect()
+}
+
+fn sort_packets(puzzle: &str) -> Result<Vec<Packet>, String> {
+    let (two, six) = dividers();
+    let mut packets = vec![two, six];
 
-    // Assume no packets are equal.
-    packets.sort_unstable();
+    packets.extend(read_packets(puzzle)?.into_iter().map(|(_, packet)| packet));
+
+    // Packets like `[1]` and `[[1]]` are equal without being the same, so sort stably to keep
+    // the result predictable. The dividers go first, so they come before any packet equal to
+    // them.
+    packets.sort_by(Packet::compare);
+
+    Ok(packets)
+}
+
+fn duplicates(puzzle: &str) -> Result<Vec<Vec<usize>>, String> {
+    // Lines holding packets that are equal to each other, which the puzzle can't put in order.
+    let mut packets = read_packets(puzzle)?;
+    packets.sort_by(|(_, left), (_, right)| left.compare(right));
+
+    let duplicates = packets.chunk_by(|(_, left), (_, right)| left.compare(right) == Equal)
+        .filter(|equal| equal.len() > 1)
+        .map(|equal| equal.iter().map(|&(line, _)| line).collect())
+        .collect();
+
+    Ok(duplicates)
+}
+
+fn decoder_key(puzzle: &str) -> Result<usize, String> {
+    let (two, six) = dividers();
+    let packets = sort_packets(puzzle)?;
 
     // Unwrap because we've added the divider packets to the list.
     let first_divider = packets.iter().position(|packet| *packet == two).unwrap() + 1;
     let second_divider = packets.iter().position(|packet| *packet == six).unwrap() + 1;
 
-    first_divider * second_divider
+    Ok(first_divider * second_divider)
 }
 
 impl Packet {
@@ -80,7 +155,12 @@ impl Packet {
     }
 
     fn compare(&self, right: &Packet) -> Ordering {
-        // TODO: Is `in_order` is transitive?
+        // This is a total order, so sorting by it works. Lists compare lexicographically, and an
+        // integer compares like the list holding just that integer, so it's as if every integer
+        // was wrapped up that way first and lexicographic order is a total order. The catch is
+        // that it's a total order on packets after wrapping, so `[1]` and `[[1]]` are `Equal`
+        // even though they're different packets, and why `Packet` isn't `Ord` when `==` tells
+        // them apart.
 
         let mut left = self.0.iter();
         let mut right = right.0.iter();
@@ -106,7 +186,7 @@ impl Packet {
                     }
                 }
                 (Some(Int(left_int)), Some(Int(right_int))) => {
-                    let order = left_int.cmp(&right_int);
+                    let order = left_int.cmp(right_int);
                     if order != Equal {
                         return order
                     }
@@ -122,15 +202,40 @@ impl Packet {
     }
 }
 
-impl PartialOrd<Packet> for Packet {
-    fn partial_cmp(&self, other: &Packet) -> Option<Ordering> {
-        Some(self.compare(other))
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // No spaces, same as the puzzle input.
+        write!(f, "[")?;
+
+        for (index, entry) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+
+            write!(f, "{}", entry)?;
+        }
+
+        write!(f, "]")
     }
 }
 
-impl Ord for Packet {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            List(packet) => write!(f, "{}", packet),
+            Int(int) => write!(f, "{}", int),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Open => write!(f, "'['"),
+            Token::Close => write!(f, "']'"),
+            Token::Comma => write!(f, "','"),
+            Token::Int(int) => write!(f, "`{}`", int),
+        }
     }
 }
 
@@ -138,65 +243,527 @@ impl FromStr for Packet {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let symbols = s.strip_prefix('[')
-            .ok_or("expect '[' at start of packet")?
-            .strip_suffix(']')
-            .ok_or("expect ']' at end of packet")?;
-
-        let mut symbols = symbols.chars();
-
-        fn go<I: Iterator<Item=char>>(symbols: &mut I) -> Result<Packet, String> {
-            // Parse the interior of a packet.
-            let mut entries: Vec<Entry> = Vec::new();
-
-            while let Some(sym) = symbols.next() {
-                match sym {
-                    '[' => {
-                        let packet = go(symbols)?;
-                        entries.push(List(packet));
+        // Recursive descent over the tokens:
+        //
+        //     packet := '[' (entry (',' entry)*)? ']'
+        //     entry  := integer | packet
+
+        let tokens = read_tokens(s)?;
+        let mut tokens = tokens.iter().copied().peekable();
+
+        type Tokens<'a> =
+            std::iter::Peekable<std::iter::Copied<std::slice::Iter<'a, (usize, Token)>>>;
+
+        // Running out of tokens is reported at the byte just past the end of the packet.
+        fn expect(expected: &str, found: Option<(usize, Token)>, end: usize) -> String {
+            match found {
+                Some((at, token)) => format!("expect {} at byte {}, found {}", expected, at, token),
+                None => format!("expect {} at byte {}, found end of packet", expected, end),
+            }
+        }
+
+        fn packet(tokens: &mut Tokens, end: usize) -> Result<Packet, String> {
+            match tokens.next() {
+                Some((_, Token::Open)) => (),
+                found => return Err(expect("'['", found, end)),
+            }
+
+            let mut entries = Vec::new();
+
+            if let Some((_, Token::Close)) = tokens.peek() {
+                tokens.next();
+                return Ok(Packet(entries));
+            }
+
+            loop {
+                entries.push(entry(tokens, end)?);
+
+                match tokens.next() {
+                    Some((_, Token::Comma)) => (),
+                    Some((_, Token::Close)) => return Ok(Packet(entries)),
+                    found => return Err(expect("',' or ']'", found, end)),
+                }
+            }
+        }
+
+        fn entry(tokens: &mut Tokens, end: usize) -> Result<Entry, String> {
+            match tokens.peek().copied() {
+                Some((_, Token::Int(int))) => {
+                    tokens.next();
+                    Ok(Int(int))
+                }
+                Some((_, Token::Open)) => Ok(List(packet(tokens, end)?)),
+                found => Err(expect("integer or '['", found, end)),
+            }
+        }
+
+        let packet = packet(&mut tokens, s.len())?;
+
+        match tokens.next() {
+            None => Ok(packet),
+            Some((at, token)) => Err(format!("unexpected {} at byte {} after packet", token, at)),
+        }
+    }
+}
+
+fn read_tokens(s: &str) -> Result<Vec<(usize, Token)>, String> {
+    // Tokens along with the byte offset they start at.
+    let mut tokens = Vec::new();
+    let mut symbols = s.char_indices().peekable();
+
+    while let Some((start, symbol)) = symbols.next() {
+        match symbol {
+            '[' => tokens.push((start, Token::Open)),
+            ']' => tokens.push((start, Token::Close)),
+            ',' => tokens.push((start, Token::Comma)),
+            _ if symbol.is_ascii_digit() => {
+                let mut end = start + 1;
+
+                while let Some(&(next, symbol)) = symbols.peek() {
+                    if !symbol.is_ascii_digit() {
+                        break;
                     }
-                    ',' => (),
-                    ']' => return Ok(Packet(entries)),
-                    int => {
-                        let mut digits = int.to_string();
-
-                        // `peekable` consumes the iterator on the first call so I sloppily
-                        // implemented lookahead with this Boolean.
-                        let mut last_digit = false;
-
-                        while let Some(sym) = symbols.next() {
-                            match sym {
-                                ',' => {
-                                    break;
-                                },
-                                ']' => {
-                                    last_digit = true;
-                                    break;
-                                },
-                                dig => {
-                                    digits.push(dig);
-                                }
-                            }
-                        }
+                    end = next + 1;
+                    symbols.next();
+                }
 
-                        let int = digits.parse::<u32>()
-                            .map_err(|_| format!("'{}' is not an integer", sym))?;
+                let digits = &s[start..end];
 
-                        entries.push(Entry::Int(int));
+                if digits.len() > 1 && digits.starts_with('0') {
+                    return Err(
+                        format!("integer `{}` at byte {} has a leading zero", digits, start)
+                    );
+                }
 
-                        if last_digit {
-                            return Ok(Packet(entries));
-                        }
+                let int = digits.parse::<u32>()
+                    .map_err(|_| format!("integer `{}` at byte {} is too big", digits, start))?;
+
+                tokens.push((start, Token::Int(int)));
+            }
+            oops => return Err(format!("unexpected '{}' at byte {}", oops, start)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn read_json_puzzle(puzzle: &str) -> Result<String, String> {
+    // Rewrite a puzzle where every line is some JSON into the usual packets. Blank lines still
+    // separate pairs, and lines stay where they were so line numbers don't change.
+    let lines = (1..).zip(puzzle.lines())
+        .map(|(number, line)| {
+            if line.trim().is_empty() {
+                return Ok(String::new());
+            }
+
+            let value = line.parse::<json::Value>()
+                .map_err(|message| format!("line {}: {}", number, message))?;
+
+            let packet = Packet::try_from(&value)
+                .map_err(|message| format!("line {}: {}", number, message))?;
+
+            Ok(packet.to_string())
+        })
+        .collect::<Result<Vec<String>, String>>()?;
+
+    Ok(lines.join("\n"))
+}
+
+impl From<&Packet> for json::Value {
+    fn from(packet: &Packet) -> Self {
+        let items = packet.0.iter()
+            .map(|entry| match entry {
+                List(packet) => json::Value::from(packet),
+                Int(int) => json::Value::Number(int.to_string()),
+            })
+            .collect();
+
+        json::Value::Array(items)
+    }
+}
+
+impl TryFrom<&json::Value> for Packet {
+    type Error = String;
+
+    fn try_from(value: &json::Value) -> Result<Self, Self::Error> {
+        // Errors say where the unsupported value is with a path like `$[1][0]`.
+        fn packet(value: &json::Value, path: &str) -> Result<Packet, String> {
+            let items = match value {
+                json::Value::Array(items) => items,
+                other => return Err(format!("expect array at {}, found {}", path, other.kind())),
+            };
+
+            items.iter()
+                .enumerate()
+                .map(|(index, item)| {
+                    let path = format!("{}[{}]", path, index);
+
+                    match item {
+                        json::Value::Array(_) => Ok(List(packet(item, &path)?)),
+                        json::Value::Number(number) => Ok(Int(int(number, &path)?)),
+                        other => Err(format!(
+                            "expect array or integer at {}, found {}", path, other.kind()
+                        )),
                     }
+                })
+                .collect::<Result<Vec<Entry>, String>>()
+                .map(Packet)
+        }
+
+        fn int(number: &str, path: &str) -> Result<u32, String> {
+            // Only plain digits, so no `-1`, `1.0` or `1e3`.
+            if !number.chars().all(|digit| digit.is_ascii_digit()) {
+                return Err(format!("expect non-negative integer at {}, found `{}`", path, number));
+            }
+
+            number.parse::<u32>()
+                .map_err(|_| format!("integer `{}` at {} is too big", number, path))
+        }
+
+        packet(value, "$")
+    }
+}
+
+mod json {
+    use std::fmt;
+    use std::str::FromStr;
+
+    // Just enough JSON to read packets from other tools. Numbers keep the text they were written
+    // with, because packets only care whether that's a small enough non-negative integer.
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum Value {
+        Null,
+        Bool(bool),
+        Number(String),
+        String(String),
+        Array(Vec<Value>),
+        Object(Vec<(String, Value)>),
+    }
+
+    impl Value {
+        pub fn kind(&self) -> &'static str {
+            match self {
+                Value::Null => "null",
+                Value::Bool(_) => "boolean",
+                Value::Number(_) => "number",
+                Value::String(_) => "string",
+                Value::Array(_) => "array",
+                Value::Object(_) => "object",
+            }
+        }
+    }
+
+    struct Reader<'a> {
+        text: &'a str,
+        // Byte offset of the next character.
+        at: usize,
+    }
+
+    impl Reader<'_> {
+        fn peek(&self) -> Option<char> {
+            self.text[self.at..].chars().next()
+        }
+
+        fn bump(&mut self) -> Option<char> {
+            let symbol = self.peek()?;
+            self.at += symbol.len_utf8();
+            Some(symbol)
+        }
+
+        fn eat(&mut self, symbol: char) -> bool {
+            let found = self.peek() == Some(symbol);
+
+            if found {
+                self.at += symbol.len_utf8();
+            }
+
+            found
+        }
+
+        fn skip_whitespace(&mut self) {
+            while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+                self.at += 1;
+            }
+        }
+
+        fn expect(&self, expected: &str) -> String {
+            match self.peek() {
+                Some(found) => {
+                    format!("expect {} at byte {}, found '{}'", expected, self.at, found)
+                }
+                None => format!("expect {} at byte {}, found end of line", expected, self.at),
+            }
+        }
+
+        fn value(&mut self) -> Result<Value, String> {
+            self.skip_whitespace();
+
+            let value = match self.peek() {
+                Some('n') => self.word("null", Value::Null)?,
+                Some('t') => self.word("true", Value::Bool(true))?,
+                Some('f') => self.word("false", Value::Bool(false))?,
+                Some('"') => Value::String(self.string()?),
+                Some('-' | '0'..='9') => Value::Number(self.number()?),
+                Some('[') => self.array()?,
+                Some('{') => self.object()?,
+                _ => return Err(self.expect("value")),
+            };
+
+            self.skip_whitespace();
+
+            Ok(value)
+        }
+
+        fn word(&mut self, word: &str, value: Value) -> Result<Value, String> {
+            if !self.text[self.at..].starts_with(word) {
+                return Err(self.expect(&format!("`{}`", word)));
+            }
+
+            self.at += word.len();
+
+            Ok(value)
+        }
+
+        fn digits(&mut self) -> usize {
+            let start = self.at;
+
+            while matches!(self.peek(), Some('0'..='9')) {
+                self.at += 1;
+            }
+
+            self.at - start
+        }
+
+        fn number(&mut self) -> Result<String, String> {
+            //     number := '-'? ('0' | [1-9] [0-9]*) ('.' [0-9]+)? ([eE] [+-]? [0-9]+)?
+            let start = self.at;
+
+            self.eat('-');
+
+            if !self.eat('0') && self.digits() == 0 {
+                return Err(self.expect("digit"));
+            }
+
+            if self.eat('.') && self.digits() == 0 {
+                return Err(self.expect("digit"));
+            }
+
+            if self.eat('e') || self.eat('E') {
+                if !self.eat('+') {
+                    self.eat('-');
+                }
+
+                if self.digits() == 0 {
+                    return Err(self.expect("digit"));
+                }
+            }
+
+            Ok(self.text[start..self.at].to_string())
+        }
+
+        fn string(&mut self) -> Result<String, String> {
+            self.eat('"');
+
+            let mut string = String::new();
+
+            loop {
+                let at = self.at;
+
+                match self.bump() {
+                    None => return Err(self.expect("'\"'")),
+                    Some('"') => return Ok(string),
+                    Some('\\') => {
+                        let escaped = match self.bump() {
+                            Some('"') => '"',
+                            Some('\\') => '\\',
+                            Some('/') => '/',
+                            Some('b') => '\u{8}',
+                            Some('f') => '\u{c}',
+                            Some('n') => '\n',
+                            Some('r') => '\r',
+                            Some('t') => '\t',
+                            Some('u') => self.unicode(at)?,
+                            _ => return Err(format!("unexpected escape at byte {}", at)),
+                        };
+
+                        string.push(escaped);
+                    }
+                    Some(symbol) if symbol < ' ' => {
+                        return Err(format!("unexpected control character at byte {}", at));
+                    }
+                    Some(symbol) => string.push(symbol),
+                }
+            }
+        }
+
+        fn hex(&mut self) -> Result<u32, String> {
+            let digits = self.text.get(self.at..self.at + 4)
+                .filter(|digits| digits.chars().all(|digit| digit.is_ascii_hexdigit()))
+                .ok_or(format!("expect four hex digits at byte {}", self.at))?;
+
+            self.at += 4;
+
+            // Can't fail, they're all hex digits.
+            Ok(u32::from_str_radix(digits, 16).unwrap())
+        }
+
+        fn unicode(&mut self, at: usize) -> Result<char, String> {
+            // Characters outside the basic multilingual plane are written as a surrogate pair.
+            let high = self.hex()?;
+
+            let code = if (0xd800..0xdc00).contains(&high) {
+                if !self.text[self.at..].starts_with("\\u") {
+                    return Err(format!("expect low surrogate at byte {}", self.at));
+                }
+
+                self.at += 2;
+                let low = self.hex()?;
+
+                if !(0xdc00..0xe000).contains(&low) {
+                    return Err(format!("expect low surrogate at byte {}", self.at - 6));
+                }
+
+                0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+            } else {
+                high
+            };
+
+            char::from_u32(code).ok_or(format!("unexpected lone surrogate at byte {}", at))
+        }
+
+        fn array(&mut self) -> Result<Value, String> {
+            self.eat('[');
+            self.skip_whitespace();
+
+            let mut items = Vec::new();
+
+            if self.eat(']') {
+                return Ok(Value::Array(items));
+            }
+
+            loop {
+                items.push(self.value()?);
+
+                if self.eat(']') {
+                    return Ok(Value::Array(items));
+                }
+
+                if !self.eat(',') {
+                    return Err(self.expect("',' or ']'"));
+                }
+            }
+        }
+
+        fn object(&mut self) -> Result<Value, String> {
+            self.eat('{');
+            self.skip_whitespace();
+
+            let mut members = Vec::new();
+
+            if self.eat('}') {
+                return Ok(Value::Object(members));
+            }
+
+            loop {
+                self.skip_whitespace();
+
+                if self.peek() != Some('"') {
+                    return Err(self.expect("string"));
+                }
+
+                let key = self.string()?;
+
+                self.skip_whitespace();
+
+                if !self.eat(':') {
+                    return Err(self.expect("':'"));
+                }
+
+                members.push((key, self.value()?));
+
+                if self.eat('}') {
+                    return Ok(Value::Object(members));
+                }
+
+                if !self.eat(',') {
+                    return Err(self.expect("',' or '}'"));
+                }
+            }
+        }
+    }
+
+    impl FromStr for Value {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let mut reader = Reader { text: s, at: 0 };
+            let value = reader.value()?;
+
+            match reader.peek() {
+                None => Ok(value),
+                Some(symbol) => {
+                    Err(format!("unexpected '{}' at byte {} after value", symbol, reader.at))
                 }
             }
+        }
+    }
 
-            Ok(Packet(entries))
+    fn write_string(f: &mut fmt::Formatter, string: &str) -> fmt::Result {
+        write!(f, "\"")?;
+
+        for symbol in string.chars() {
+            match symbol {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                '\r' => write!(f, "\\r")?,
+                '\t' => write!(f, "\\t")?,
+                _ if symbol < ' ' => write!(f, "\\u{:04x}", symbol as u32)?,
+                _ => write!(f, "{}", symbol)?,
+            }
         }
 
-        go(&mut symbols)
+        write!(f, "\"")
     }
 
+    impl fmt::Display for Value {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            // Compact, like the packets.
+            match self {
+                Value::Null => write!(f, "null"),
+                Value::Bool(bool) => write!(f, "{}", bool),
+                Value::Number(number) => write!(f, "{}", number),
+                Value::String(string) => write_string(f, string),
+                Value::Array(items) => {
+                    write!(f, "[")?;
+
+                    for (index, item) in items.iter().enumerate() {
+                        if index > 0 {
+                            write!(f, ",")?;
+                        }
+
+                        write!(f, "{}", item)?;
+                    }
+
+                    write!(f, "]")
+                }
+                Value::Object(members) => {
+                    write!(f, "{{")?;
+
+                    for (index, (key, value)) in members.iter().enumerate() {
+                        if index > 0 {
+                            write!(f, ",")?;
+                        }
+
+                        write_string(f, key)?;
+                        write!(f, ":{}", value)?;
+                    }
+
+                    write!(f, "}}")
+                }
+            }
+        }
+    }
 }
 
 #[test]
@@ -360,3 +927,248 @@ fn compare_nested_success() -> Result<(), String> {
     assert!("[[1],6]".parse::<Packet>()?.in_order(&"[[2],4]".parse::<Packet>()?));
     Ok(())
 }
+
+#[test]
+fn print_packets_back() -> Result<(), String> {
+    assert_eq!(Packet(vec![Int(1), List(Packet(vec![Int(2), Int(3)]))]).to_string(), "[1,[2,3]]");
+    assert_eq!(Packet(vec![List(Packet(vec![]))]).to_string(), "[[]]");
+
+    let puzzle = fs::read_to_string("input.txt").unwrap();
+
+    for line in puzzle.lines().filter(|line| !line.is_empty()) {
+        assert_eq!(line.parse::<Packet>()?.to_string(), line);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn every_packet(entries: usize, ints: &[u32]) -> Vec<Packet> {
+    // Every packet with at most this many entries, counting the ones in nested lists too.
+    let mut packets = Vec::new();
+
+    for used in 0..=entries {
+        packets.extend(exactly(used, ints));
+    }
+
+    packets
+}
+
+#[cfg(test)]
+fn exactly(entries: usize, ints: &[u32]) -> Vec<Packet> {
+    if entries == 0 {
+        return vec![Packet(Vec::new())];
+    }
+
+    let mut packets = Vec::new();
+
+    // The first entry is an integer or a list, which counts as one more entry than it holds, and
+    // the rest of the packet gets what's left.
+    for first in 1..=entries {
+        let mut heads = exactly(first - 1, ints).into_iter().map(List).collect::<Vec<Entry>>();
+
+        if first == 1 {
+            heads.extend(ints.iter().map(|&int| Int(int)));
+        }
+
+        for head in heads {
+            for Packet(rest) in exactly(entries - first, ints) {
+                packets.push(Packet([vec![head.clone()], rest].concat()));
+            }
+        }
+    }
+
+    packets
+}
+
+#[test]
+fn print_and_read_every_packet() -> Result<(), String> {
+    // Big numbers too, to make sure all the digits come back.
+    for packet in every_packet(5, &[0, 7, 10, u32::MAX]) {
+        let printed = packet.to_string();
+
+        assert_eq!(printed.parse::<Packet>()?, packet, "{} doesn't read back", printed);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn reject_malformed_packets() {
+    let malformed = [
+        ("", "expect '[' at byte 0, found end of packet"),
+        ("1", "expect '[' at byte 0, found `1`"),
+        ("[1,2", "expect ',' or ']' at byte 4, found end of packet"),
+        ("[[1],2", "expect ',' or ']' at byte 6, found end of packet"),
+        ("[1,2]]", "unexpected ']' at byte 5 after packet"),
+        ("[[1]][2]", "unexpected '[' at byte 5 after packet"),
+        ("[1,,2]", "expect integer or '[' at byte 3, found ','"),
+        ("[,1]", "expect integer or '[' at byte 1, found ','"),
+        ("[1,]", "expect integer or '[' at byte 3, found ']'"),
+        ("[1 2]", "unexpected ' ' at byte 2"),
+        ("[1,a]", "unexpected 'a' at byte 3"),
+        ("[-1]", "unexpected '-' at byte 1"),
+        ("[4294967296]", "integer `4294967296` at byte 1 is too big"),
+        ("[1, 2]", "unexpected ' ' at byte 3"),
+        ("[1,[007]]", "integer `007` at byte 4 has a leading zero"),
+    ];
+
+    for (packet, message) in malformed {
+        assert_eq!(packet.parse::<Packet>(), Err(message.to_string()), "reading {:?}", packet);
+    }
+
+    assert_eq!(
+        in_order_pairs("[1]\n[2]\n\n[3]\n[[4]"),
+        Err("pair 2, right packet: expect ',' or ']' at byte 4, found end of packet".to_string())
+    );
+    assert_eq!(in_order_pairs("[1]\n[2]\n\n[3]"), Err("pair 2: expect right packet".to_string()));
+    assert_eq!(
+        decoder_key("[1]\n[2]\n\n[3]\n[4,]"),
+        Err("line 5: expect integer or '[' at byte 3, found ']'".to_string())
+    );
+}
+
+#[test]
+fn compare_is_total_order() {
+    let packets = every_packet(3, &[0, 1]);
+
+    for a in &packets {
+        assert_eq!(a.compare(a), Equal, "{} isn't equal to itself", a);
+
+        for b in &packets {
+            assert_eq!(a.compare(b), b.compare(a).reverse(), "{} and {} disagree", a, b);
+        }
+    }
+
+    let mut equal = 0;
+
+    for a in &packets {
+        for b in &packets {
+            for c in &packets {
+                let (ab, bc, ac) = (a.compare(b), b.compare(c), a.compare(c));
+
+                if ab == bc {
+                    assert_eq!(ac, ab, "{} to {} to {} isn't transitive", a, b, c);
+                }
+
+                if ab == Equal {
+                    assert_eq!(ac, bc, "{} and {} aren't interchangeable", a, b);
+                    equal += 1;
+                }
+            }
+        }
+    }
+
+    // Packets being equal to themselves only accounts for `len * len` of these, so there have
+    // to be different packets that compare equal too.
+    assert!(equal > packets.len() * packets.len());
+}
+
+#[test]
+fn sort_equal_packets() -> Result<(), String> {
+    let puzzle = "[1]\n[[1]]\n\n[[2]]\n[2,[3]]\n\n[[[2]]]\n[1]";
+
+    assert_eq!(duplicates(puzzle)?, vec![vec![1, 2, 8], vec![4, 7]]);
+
+    let sorted = sort_packets(puzzle)?.iter().map(Packet::to_string).collect::<Vec<String>>();
+    assert_eq!(
+        sorted,
+        ["[1]", "[[1]]", "[1]", "[[2]]", "[[2]]", "[[[2]]]", "[2,[3]]", "[[6]]"]
+    );
+
+    // The divider is still the first `[[2]]`, at index 3, and `[[6]]` is last.
+    assert_eq!(decoder_key(puzzle)?, 4 * 8);
+
+    // Equal isn't the same packet.
+    let (one, wrapped) = ("[1]".parse::<Packet>()?, "[[1]]".parse::<Packet>()?);
+    assert_eq!(one.compare(&wrapped), Equal);
+    assert_ne!(one, wrapped);
+
+    let puzzle = fs::read_to_string("input.txt").unwrap();
+    assert!(duplicates(&puzzle)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn read_json_values() -> Result<(), String> {
+    let text = r#" {"a" : [1, -2.5e-3, "x\n\u00e9\ud83d\ude00"], "b": [null, true, false, {}]} "#;
+    let value = text.parse::<json::Value>()?;
+
+    assert_eq!(
+        value.to_string(),
+        "{\"a\":[1,-2.5e-3,\"x\\n\u{e9}\u{1f600}\"],\"b\":[null,true,false,{}]}"
+    );
+    assert_eq!(value.to_string().parse::<json::Value>()?, value);
+
+    let malformed = [
+        ("", "expect value at byte 0, found end of line"),
+        ("[1,]", "expect value at byte 3, found ']'"),
+        ("[1 2]", "expect ',' or ']' at byte 3, found '2'"),
+        ("{1:2}", "expect string at byte 1, found '1'"),
+        ("{\"a\" 2}", "expect ':' at byte 5, found '2'"),
+        ("[01]", "expect ',' or ']' at byte 2, found '1'"),
+        ("[1.]", "expect digit at byte 3, found ']'"),
+        ("[tru]", "expect `true` at byte 1, found 't'"),
+        ("\"abc", "expect '\"' at byte 4, found end of line"),
+        ("\"\\x\"", "unexpected escape at byte 1"),
+        ("\"\\ud83d\"", "expect low surrogate at byte 7"),
+        ("[] []", "unexpected '[' at byte 3 after value"),
+    ];
+
+    for (text, message) in malformed {
+        assert_eq!(text.parse::<json::Value>(), Err(message.to_string()), "reading {:?}", text);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn convert_packets_to_and_from_json() -> Result<(), String> {
+    let puzzle = fs::read_to_string("input.txt").unwrap();
+
+    for line in puzzle.lines().filter(|line| !line.is_empty()) {
+        let packet = line.parse::<Packet>()?;
+        let value = json::Value::from(&packet);
+
+        assert_eq!(value.to_string(), line);
+        assert_eq!(Packet::try_from(&line.parse::<json::Value>()?)?, packet);
+    }
+
+    let unsupported = [
+        ("{}", "expect array at $, found object"),
+        ("3", "expect array at $, found number"),
+        ("[1,[2,\"3\"]]", "expect array or integer at $[1][1], found string"),
+        ("[[null]]", "expect array or integer at $[0][0], found null"),
+        ("[-1]", "expect non-negative integer at $[0], found `-1`"),
+        ("[1.0]", "expect non-negative integer at $[0], found `1.0`"),
+        ("[[1e3]]", "expect non-negative integer at $[0][0], found `1e3`"),
+        ("[4294967296]", "integer `4294967296` at $[0] is too big"),
+    ];
+
+    for (text, message) in unsupported {
+        let value = text.parse::<json::Value>()?;
+        assert_eq!(Packet::try_from(&value), Err(message.to_string()), "converting {}", text);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn read_sample_as_json() -> Result<(), String> {
+    let sample = fs::read_to_string("sample.txt").unwrap();
+
+    // Spaces are fine in JSON.
+    let spaced = sample.replace(',', ", ");
+    let puzzle = read_json_puzzle(&spaced)?;
+
+    assert_eq!(in_order_pairs(&puzzle)?, in_order_pairs(&sample)?);
+    assert_eq!(decoder_key(&puzzle)?, 140);
+
+    assert_eq!(
+        read_json_puzzle("[1]\n[2]\n\n[3]\n{\"four\": 4}"),
+        Err("line 5: expect array at $, found object".to_string())
+    );
+
+    Ok(())
+}